@@ -0,0 +1,193 @@
+use std::cmp;
+use std::io::{self, Write};
+
+use traits::*;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Default maximum frame length accepted by a `DelimitedCodec`, 64 KiB.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Frames an inner codec by a delimiter byte sequence, such as `\0` or `\r\n\r\n`.
+///
+/// The inner codec is handed one complete frame at a time. Encoded frames must not contain the
+/// delimiter themselves.
+pub struct DelimitedCodec<C> {
+    codec: C,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,
+    max_frame_len: usize,
+    strip_delimiter: bool,
+}
+
+impl<C> DelimitedCodec<C> {
+    /// Panics if `delimiter` is empty.
+    pub fn new(codec: C, delimiter: Vec<u8>) -> DelimitedCodec<C> {
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+
+        DelimitedCodec {
+            codec,
+            buffer: Vec::with_capacity(DEFAULT_CAPACITY),
+            delimiter,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            strip_delimiter: true,
+        }
+    }
+
+    /// Sets the maximum frame length, not counting the delimiter. Longer frames fail to decode or
+    /// encode instead of being buffered. A partial frame is searched for the delimiter again
+    /// every time more of it arrives, so this also bounds the cost of decoding.
+    pub fn set_max_frame_len(&mut self, len: usize) {
+        self.max_frame_len = len;
+    }
+
+    /// Sets whether the delimiter is removed from frames before they are decoded by the inner
+    /// codec. Defaults to true.
+    pub fn set_strip_delimiter(&mut self, strip: bool) {
+        self.strip_delimiter = strip;
+    }
+
+    fn check_frame_len(&self, len: usize, kind: io::ErrorKind) -> io::Result<()> {
+        if len > self.max_frame_len {
+            let msg = format!("frame length {} exceeds maximum of {}", len, self.max_frame_len);
+            return Err(io::Error::new(kind, msg))
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the first delimiter in `buffer`, looking no further than the end of
+    /// a frame of the maximum length. No position is kept between calls, so `decode` may be
+    /// handed any buffer.
+    fn find_delimiter(&self, buffer: &[u8]) -> Option<usize> {
+        let len = cmp::min(buffer.len(), self.max_frame_len.saturating_add(self.delimiter.len()));
+        buffer[..len].windows(self.delimiter.len())
+            .position(|window| window == &self.delimiter[..])
+    }
+}
+
+impl<C: Codec<Vec<u8>>, B: Write> Codec<B> for DelimitedCodec<C> {
+    type Input = C::Input;
+    type Output = C::Output;
+
+    fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
+        self.buffer.clear();
+        self.codec.encode(&mut self.buffer, input)?;
+        self.check_frame_len(self.buffer.len(), io::ErrorKind::InvalidInput)?;
+
+        buffer.write_all(&self.buffer[..])?;
+        buffer.write_all(&self.delimiter[..])
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        let end = match self.find_delimiter(buffer) {
+            Some(end) => end,
+            None => {
+                // Allow for a partially received delimiter at the end
+                let len = buffer.len().saturating_sub(self.delimiter.len() - 1);
+                self.check_frame_len(len, io::ErrorKind::InvalidData)?;
+                return Ok(None)
+            },
+        };
+        self.check_frame_len(end, io::ErrorKind::InvalidData)?;
+
+        let consumed = end + self.delimiter.len();
+        let frame = if self.strip_delimiter {
+            &buffer[..end]
+        } else {
+            &buffer[..consumed]
+        };
+
+        // The frame is consumed as a whole, regardless of how much of it the inner codec used
+        match self.codec.decode(frame)? {
+            Some((_, output)) => Ok(Some((consumed, output))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete frame payload")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use test_helpers::FakeCodec;
+
+    fn decode(codec: &mut DelimitedCodec<FakeCodec>, buffer: &[u8]) -> io::Result<Option<(usize, Vec<u8>)>> {
+        <DelimitedCodec<_> as Codec<Vec<u8>>>::decode(codec, buffer)
+    }
+
+    #[test]
+    fn test_codec() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), b"\r\n\r\n".to_vec());
+        let mut buffer = Vec::new();
+
+        expect(&codec.encode(&mut buffer, vec!(1, 2, 3))).to(be_ok());
+        expect(&codec.encode(&mut buffer, vec!(4))).to(be_ok());
+        expect(&buffer).to(equal(&b"\x01\x02\x03\r\n\r\n\x04\r\n\r\n".to_vec()));
+
+        let (num, output) = decode(&mut codec, &buffer[..]).unwrap().unwrap();
+        expect(&num).to(equal(&7));
+        expect(&output).to(equal(&vec!(1, 2, 3)));
+
+        let (num, output) = decode(&mut codec, &buffer[7..]).unwrap().unwrap();
+        expect(&num).to(equal(&5));
+        expect(&output).to(equal(&vec!(4)));
+    }
+
+    #[test]
+    fn test_codec_partial_delimiter() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), b"\r\n\r\n".to_vec());
+        let buffer = b"abc\r\n\r\n";
+
+        expect(&decode(&mut codec, &buffer[..5]).unwrap()).to(be_none());
+        expect(&decode(&mut codec, &buffer[..6]).unwrap()).to(be_none());
+
+        let (num, output) = decode(&mut codec, &buffer[..]).unwrap().unwrap();
+        expect(&num).to(equal(&7));
+        expect(&output).to(equal(&b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_codec_unrelated_buffers() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), vec!(0));
+
+        // A partial frame is not remembered, the next buffer is searched from its start
+        expect(&decode(&mut codec, b"abc").unwrap()).to(be_none());
+        let (num, output) = decode(&mut codec, b"x\0yz").unwrap().unwrap();
+        expect(&num).to(equal(&2));
+        expect(&output).to(equal(&b"x".to_vec()));
+    }
+
+    #[test]
+    fn test_codec_keep_delimiter() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), vec!(0));
+        codec.set_strip_delimiter(false);
+
+        let (num, output) = decode(&mut codec, b"ab\0cd").unwrap().unwrap();
+        expect(&num).to(equal(&3));
+        expect(&output).to(equal(&b"ab\0".to_vec()));
+    }
+
+    #[test]
+    fn test_codec_max_frame_len() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), vec!(0));
+        codec.set_max_frame_len(2);
+        let mut buffer = Vec::new();
+
+        expect(&decode(&mut codec, b"ab\0").unwrap()).to(be_some());
+        expect(&decode(&mut codec, b"abc\0")).to(be_err());
+        expect(&decode(&mut codec, b"abc")).to(be_err());
+
+        let res = codec.encode(&mut buffer, vec!(1, 2, 3));
+        expect(&res).to(be_err());
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn test_codec_default_max_frame_len() {
+        let mut codec = DelimitedCodec::new(FakeCodec::new(), vec!(0));
+        let buffer = vec!(1; DEFAULT_MAX_FRAME_LEN + 1);
+
+        let res = decode(&mut codec, &buffer[..]);
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+    }
+}
@@ -1,21 +1,262 @@
 use std::io::{self, Write};
-use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt, ReadBytesExt};
 
 use traits::*;
 
 const DEFAULT_CAPACITY: usize = 1024;
+const MAX_VARINT_LEN: usize = 10;
+
+/// Default maximum payload length accepted by a `FixedLengthCodec`, 8 MiB.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// Encoding of the length field in a frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthField {
+    U8,
+    U16,
+    U32,
+    U64,
+    /// Protobuf style base 128 varint, least significant group first.
+    Varint,
+}
+
+/// Byte order of fixed width length fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// Describes the header in front of every frame: `header_offset` bytes, followed by the length
+/// field.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    length_field: LengthField,
+    endian: Endian,
+    length_adjustment: isize,
+    header_offset: usize,
+    max_frame_len: usize,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder {
+            length_field: LengthField::U32,
+            endian: Endian::Big,
+            length_adjustment: 0,
+            header_offset: 0,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Width and encoding of the length field. Defaults to `LengthField::U32`.
+    pub fn length_field(mut self, field: LengthField) -> Builder {
+        self.length_field = field;
+        self
+    }
+
+    /// Byte order of the length field. Ignored for varints. Defaults to `Endian::Big`.
+    pub fn endian(mut self, endian: Endian) -> Builder {
+        self.endian = endian;
+        self
+    }
+
+    /// Value added to the length field to get the payload length. For example, a protocol whose
+    /// length counts a 4 byte header as well as the payload needs an adjustment of -4.
+    pub fn length_adjustment(mut self, adjustment: isize) -> Builder {
+        self.length_adjustment = adjustment;
+        self
+    }
+
+    /// Number of bytes in front of the length field. They are skipped when decoding and written
+    /// as zeros when encoding.
+    pub fn header_offset(mut self, offset: usize) -> Builder {
+        self.header_offset = offset;
+        self
+    }
+
+    /// Maximum payload length, not counting the header. Larger frames fail to decode or encode
+    /// instead of being buffered.
+    pub fn max_frame_len(mut self, len: usize) -> Builder {
+        self.max_frame_len = len;
+        self
+    }
+
+    pub fn build<C>(self, codec: C) -> FixedLengthCodec<C> {
+        FixedLengthCodec {
+            codec,
+            buffer: Vec::with_capacity(DEFAULT_CAPACITY),
+            format: self,
+        }
+    }
+}
 
 pub struct FixedLengthCodec<C> {
     codec: C,
     buffer: Vec<u8>,
+    format: Builder,
 }
 
 impl<C> FixedLengthCodec<C> {
+    /// Creates a codec using a big endian u32 length prefix.
     pub fn new(codec: C) -> FixedLengthCodec<C> {
-        FixedLengthCodec {
-            codec: codec,
-            buffer: Vec::with_capacity(DEFAULT_CAPACITY),
+        Builder::new().build(codec)
+    }
+
+    /// Sets the maximum payload length, not counting the header. Larger frames fail to decode or
+    /// encode instead of being buffered.
+    pub fn set_max_frame_len(&mut self, len: usize) {
+        self.format.max_frame_len = len;
+    }
+
+    fn check_frame_len(&self, len: u64, kind: io::ErrorKind) -> io::Result<()> {
+        if len > self.format.max_frame_len as u64 {
+            let msg = format!("frame length {} exceeds maximum of {}", len, self.format.max_frame_len);
+            return Err(io::Error::new(kind, msg))
         }
+
+        Ok(())
+    }
+
+    /// Parses the frame header, returning the header and payload lengths.
+    fn read_header(&self, buffer: &[u8]) -> io::Result<Option<(usize, usize)>> {
+        let offset = self.format.header_offset;
+        if buffer.len() < offset {
+            return Ok(None)
+        }
+
+        let (field_len, value) = match read_length(&buffer[offset..], self.format.length_field, self.format.endian)? {
+            Some(field) => field,
+            None => return Ok(None),
+        };
+
+        let adjustment = self.format.length_adjustment;
+        let len = if adjustment >= 0 {
+            value.checked_add(adjustment as u64)
+        } else {
+            value.checked_sub(adjustment.unsigned_abs() as u64)
+        };
+        let len = match len {
+            Some(len) => len,
+            None => {
+                let msg = format!("length field {} is out of range", value);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+            },
+        };
+        self.check_frame_len(len, io::ErrorKind::InvalidData)?;
+
+        Ok(Some((offset + field_len, len as usize)))
+    }
+
+    fn write_header<B: Write>(&self, buffer: &mut B, len: usize) -> io::Result<()> {
+        let adjustment = self.format.length_adjustment;
+        let value = if adjustment >= 0 {
+            (len as u64).checked_sub(adjustment as u64)
+        } else {
+            (len as u64).checked_add(adjustment.unsigned_abs() as u64)
+        };
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let msg = format!("frame length {} cannot be represented in the length field", len);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+            },
+        };
+
+        for _ in 0..self.format.header_offset {
+            buffer.write_u8(0)?;
+        }
+        write_length(buffer, value, self.format.length_field, self.format.endian)
+    }
+}
+
+/// Reads a length field, returning the number of bytes it took up and its value.
+fn read_length(mut buffer: &[u8], field: LengthField, endian: Endian) -> io::Result<Option<(usize, u64)>> {
+    let width = match field {
+        LengthField::U8 => 1,
+        LengthField::U16 => 2,
+        LengthField::U32 => 4,
+        LengthField::U64 => 8,
+        LengthField::Varint => return read_varint(buffer),
+    };
+
+    if buffer.len() < width {
+        return Ok(None)
+    }
+
+    let value = match (field, endian) {
+        (LengthField::U8, _) => buffer.read_u8()? as u64,
+        (LengthField::U16, Endian::Big) => buffer.read_u16::<BigEndian>()? as u64,
+        (LengthField::U16, Endian::Little) => buffer.read_u16::<LittleEndian>()? as u64,
+        (LengthField::U32, Endian::Big) => buffer.read_u32::<BigEndian>()? as u64,
+        (LengthField::U32, Endian::Little) => buffer.read_u32::<LittleEndian>()? as u64,
+        (LengthField::U64, Endian::Big) => buffer.read_u64::<BigEndian>()?,
+        (LengthField::U64, Endian::Little) => buffer.read_u64::<LittleEndian>()?,
+        (LengthField::Varint, _) => unreachable!(),
+    };
+
+    Ok(Some((width, value)))
+}
+
+fn read_varint(buffer: &[u8]) -> io::Result<Option<(usize, u64)>> {
+    let mut value = 0u64;
+    for (i, &byte) in buffer.iter().take(MAX_VARINT_LEN).enumerate() {
+        // The tenth byte may only hold the single remaining bit of a u64
+        if i == MAX_VARINT_LEN - 1 && byte > 1 {
+            break
+        }
+
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((i + 1, value)))
+        }
+    }
+
+    if buffer.len() < MAX_VARINT_LEN {
+        return Ok(None)
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint length field overflows u64"))
+}
+
+fn write_length<B: Write>(buffer: &mut B, value: u64, field: LengthField, endian: Endian) -> io::Result<()> {
+    let max = match field {
+        LengthField::U8 => u8::MAX as u64,
+        LengthField::U16 => u16::MAX as u64,
+        LengthField::U32 => u32::MAX as u64,
+        LengthField::U64 | LengthField::Varint => u64::MAX,
+    };
+    if value > max {
+        let msg = format!("frame length {} does not fit in a {:?} length field", value, field);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    }
+
+    match (field, endian) {
+        (LengthField::U8, _) => buffer.write_u8(value as u8),
+        (LengthField::U16, Endian::Big) => buffer.write_u16::<BigEndian>(value as u16),
+        (LengthField::U16, Endian::Little) => buffer.write_u16::<LittleEndian>(value as u16),
+        (LengthField::U32, Endian::Big) => buffer.write_u32::<BigEndian>(value as u32),
+        (LengthField::U32, Endian::Little) => buffer.write_u32::<LittleEndian>(value as u32),
+        (LengthField::U64, Endian::Big) => buffer.write_u64::<BigEndian>(value),
+        (LengthField::U64, Endian::Little) => buffer.write_u64::<LittleEndian>(value),
+        (LengthField::Varint, _) => write_varint(buffer, value),
+    }
+}
+
+fn write_varint<B: Write>(buffer: &mut B, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return buffer.write_u8(byte)
+        }
+        buffer.write_u8(byte | 0x80)?;
     }
 }
 
@@ -24,44 +265,38 @@ impl<C: Codec<Vec<u8>>, B: Write> Codec<B> for FixedLengthCodec<C> {
     type Output = C::Output;
 
     fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
-        let codec = &mut self.codec;
-        let inner_buf = &mut self.buffer;
-        try!(codec.encode(inner_buf, input));
-        let len = inner_buf.len();
-        debug_assert!(len <= ::std::u32::MAX as usize);
+        self.buffer.clear();
+        self.codec.encode(&mut self.buffer, input)?;
+        let len = self.buffer.len();
+        self.check_frame_len(len as u64, io::ErrorKind::InvalidInput)?;
 
-        try!(buffer.write_u32::<BigEndian>(len as u32));
-        buffer.write_all(&inner_buf[..])
+        self.write_header(buffer, len)?;
+        buffer.write_all(&self.buffer[..])
     }
 
-    fn decode(&mut self, mut buffer: &[u8]) -> Option<(usize, Self::Output)> {
-        // Not enough bytes to read u32
-        if buffer.len() < 4 {
-            return None;
-        }
-
-        let len = match buffer.read_u32::<BigEndian>() {
-            Err(_) => {
-                warn!("failed to read u32 value");
-                return None
-            },
-            Ok(n) => n,
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        let (header_len, len) = match self.read_header(buffer)? {
+            Some(header) => header,
+            None => return Ok(None),
         };
 
-        if buffer.len() < (len as usize) {
-            return None
+        if buffer.len() - header_len < len {
+            return Ok(None)
         }
 
-        self.codec.decode(&buffer[..len as usize])
+        // The frame is consumed as a whole, regardless of how much of it the inner codec used
+        let payload = &buffer[header_len..header_len + len];
+        match self.codec.decode(payload)? {
+            Some((_, output)) => Ok(Some((header_len + len, output))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete frame payload")),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use traits::*;
     use ferrous::dsl::*;
-    use std::io::{Write};
     use test_helpers::FakeCodec;
 
     #[test]
@@ -78,6 +313,8 @@ mod tests {
 
         let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
 
+        expect(&decode).to(be_ok());
+        let decode = decode.unwrap();
         expect(&decode).to(be_some());
         let (_, decoded_output) = decode.unwrap();
         expect(&decoded_output).to(equal(&input));
@@ -94,7 +331,8 @@ mod tests {
         expect(&res).to(be_ok());
 
         let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..1]);
-        expect(&decode).to(be_none());
+        expect(&decode).to(be_ok());
+        expect(&decode.unwrap()).to(be_none());
     }
 
     #[test]
@@ -108,6 +346,151 @@ mod tests {
         expect(&res).to(be_ok());
 
         let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..8]);
-        expect(&decode).to(be_none());
+        expect(&decode).to(be_ok());
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_codec_consumes_header() {
+        let mut codec = FixedLengthCodec::new(FakeCodec::new());
+        let mut buffer = Vec::new();
+
+        expect(&codec.encode(&mut buffer, vec!(1,2,3))).to(be_ok());
+        expect(&codec.encode(&mut buffer, vec!(4,5))).to(be_ok());
+        expect(&buffer.len()).to(equal(&13));
+
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        let (num, output) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&7));
+        expect(&output).to(equal(&vec!(1,2,3)));
+
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[num..]);
+        let (num, output) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&6));
+        expect(&output).to(equal(&vec!(4,5)));
+    }
+
+    #[test]
+    fn test_codec_decode_frame_too_large() {
+        let mut codec = FixedLengthCodec::new(FakeCodec::new());
+        codec.set_max_frame_len(4);
+        let buffer = [0, 0, 0, 5];
+
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        expect(&decode).to(be_err());
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_codec_encode_frame_too_large() {
+        let mut codec = FixedLengthCodec::new(FakeCodec::new());
+        codec.set_max_frame_len(4);
+        let mut buffer = Vec::new();
+
+        let res = codec.encode(&mut buffer, vec!(1,2,3,4,5));
+        expect(&res).to(be_err());
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidInput));
+        expect(&buffer.len()).to(equal(&0));
+    }
+
+    fn roundtrip(codec: &mut FixedLengthCodec<FakeCodec>, input: Vec<u8>) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        expect(&codec.encode(&mut buffer, input.clone())).to(be_ok());
+
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(codec, &buffer[..]);
+        let (num, output) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&buffer.len()));
+        expect(&output).to(equal(&input));
+        buffer
+    }
+
+    #[test]
+    fn test_builder_u16_little_endian() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::U16)
+            .endian(Endian::Little)
+            .build(FakeCodec::new());
+
+        let buffer = roundtrip(&mut codec, vec!(7; 258));
+        expect(&&buffer[..2]).to(equal(&&[2, 1][..]));
+    }
+
+    #[test]
+    fn test_builder_u64() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::U64)
+            .build(FakeCodec::new());
+
+        let buffer = roundtrip(&mut codec, vec!(1, 2, 3));
+        expect(&&buffer[..8]).to(equal(&&[0, 0, 0, 0, 0, 0, 0, 3][..]));
+    }
+
+    #[test]
+    fn test_builder_varint() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::Varint)
+            .build(FakeCodec::new());
+
+        let buffer = roundtrip(&mut codec, vec!(1; 300));
+        expect(&&buffer[..2]).to(equal(&&[0xac, 0x02][..]));
+
+        // Only the first byte of the varint has arrived
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..1]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_builder_varint_overflow() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::Varint)
+            .build(FakeCodec::new());
+        let buffer = [0xff; 11];
+
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        expect(&decode).to(be_err());
+    }
+
+    #[test]
+    fn test_builder_length_includes_header() {
+        let mut codec = Builder::new()
+            .length_adjustment(-4)
+            .build(FakeCodec::new());
+
+        let buffer = roundtrip(&mut codec, vec!(1, 2, 3));
+        expect(&&buffer[..4]).to(equal(&&[0, 0, 0, 7][..]));
+
+        // A length smaller than the header itself is invalid
+        let buffer = [0, 0, 0, 2];
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        expect(&decode).to(be_err());
+    }
+
+    #[test]
+    fn test_builder_header_offset() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::U8)
+            .header_offset(2)
+            .build(FakeCodec::new());
+
+        let buffer = roundtrip(&mut codec, vec!(1, 2, 3));
+        expect(&buffer).to(equal(&vec!(0, 0, 3, 1, 2, 3)));
+
+        let buffer = [9, 9, 2, 4, 5];
+        let decode = <FixedLengthCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        let (num, output) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&5));
+        expect(&output).to(equal(&vec!(4, 5)));
+    }
+
+    #[test]
+    fn test_builder_length_field_too_small() {
+        let mut codec = Builder::new()
+            .length_field(LengthField::U8)
+            .build(FakeCodec::new());
+        let mut buffer = Vec::new();
+
+        let res = codec.encode(&mut buffer, vec!(0; 256));
+        expect(&res).to(be_err());
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidInput));
     }
 }
@@ -1,26 +1,161 @@
 use serde::{Serialize, Deserialize};
-use serde_json::{self, error};
+use serde_json;
 
+use std::cmp;
 use std::marker::PhantomData;
 use std::io::{self, Write};
 
 use traits::*;
 
+/// Default maximum length of a value accepted by a `JsonCodec`, 1 MiB.
+pub const DEFAULT_MAX_VALUE_LEN: usize = 1024 * 1024;
+
+/// How consecutive JSON values are separated on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Values follow each other directly, optionally separated by whitespace. Objects, arrays
+    /// and strings delimit themselves, while numbers and literals are only complete once the
+    /// next whitespace or value arrives.
+    Concatenated,
+    /// Each value is followed by a newline. Blank lines are skipped.
+    Newline,
+}
+
+/// Decodes one JSON value at a time from a byte stream.
 pub struct JsonCodec<T> {
+    framing: Framing,
+    max_value_len: usize,
     rust_type: PhantomData<* const T>,
 }
 
 impl<T> JsonCodec<T> {
     pub fn new() -> JsonCodec<T> {
+        JsonCodec::with_framing(Framing::Concatenated)
+    }
+
+    /// Creates a codec for newline delimited JSON.
+    pub fn newline_delimited() -> JsonCodec<T> {
+        JsonCodec::with_framing(Framing::Newline)
+    }
+
+    pub fn with_framing(framing: Framing) -> JsonCodec<T> {
         JsonCodec {
+            framing,
+            max_value_len: DEFAULT_MAX_VALUE_LEN,
             rust_type: PhantomData,
         }
     }
+
+    /// Sets the maximum length of a value, including any whitespace before it. Longer values fail
+    /// to decode instead of being buffered. A partial value is scanned again from its start every
+    /// time more of it arrives, so this also bounds the cost of decoding.
+    pub fn set_max_value_len(&mut self, len: usize) {
+        self.max_value_len = len;
+    }
+
+    fn value_too_long(&self) -> io::Error {
+        let msg = format!("json value exceeds maximum length of {}", self.max_value_len);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n' || b == b'\r'
+}
+
+/// Returns the length of the first value in `buffer`, including leading whitespace, or `None` if
+/// it has not been fully received yet.
+fn value_len(buffer: &[u8]) -> io::Result<Option<usize>> {
+    let start = match buffer.iter().position(|&b| !is_whitespace(b)) {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+
+    let value = &buffer[start..];
+    let len = match value[0] {
+        b'{' | b'[' => nested_len(value),
+        b'"' => string_len(value),
+        b'}' | b']' | b',' | b':' => {
+            let msg = format!("unexpected character '{}' at start of json value", value[0] as char);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+        },
+        // Numbers and literals end at the next whitespace or structural character
+        _ => value.iter().position(|&b| {
+            is_whitespace(b) || b"{}[],:\"".contains(&b)
+        }),
+    };
+
+    Ok(len.map(|len| start + len))
+}
+
+/// Length of an object or array, found by matching brackets outside of strings.
+fn nested_len(value: &[u8]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, &b) in value.iter().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                in_string = false;
+            }
+            continue
+        }
+
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1)
+                }
+            },
+            _ => {},
+        }
+    }
+
+    None
+}
+
+/// Length of a string, including both quotes.
+fn string_len(value: &[u8]) -> Option<usize> {
+    let mut escaped = false;
+    for (i, &b) in value.iter().enumerate().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if b == b'\\' {
+            escaped = true;
+        } else if b == b'"' {
+            return Some(i + 1)
+        }
+    }
+
+    None
+}
+
+/// Returns the length of the first non blank line in `buffer` and the position of its
+/// terminating newline, or `None` if no complete line has been received yet.
+fn line_len(buffer: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    while let Some(pos) = buffer[start..].iter().position(|&b| b == b'\n') {
+        let end = start + pos;
+        if buffer[start..end].iter().any(|&b| !is_whitespace(b)) {
+            return Some((start, end))
+        }
+        start = end + 1;
+    }
+
+    None
 }
 
 impl<T: Deserialize + Serialize, B: Write> Codec<B> for JsonCodec<T> {
     type Input = T;
-    type Output = error::Result<T>;
+    type Output = T;
 
     fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
         use std::io::ErrorKind::*;
@@ -28,11 +163,36 @@ impl<T: Deserialize + Serialize, B: Write> Codec<B> for JsonCodec<T> {
 
         serde_json::to_writer(buffer, &input).or_else(|e| {
             Err(Error::new(Other, e))
-        })
+        })?;
+
+        match self.framing {
+            Framing::Newline => buffer.write_all(b"\n"),
+            Framing::Concatenated => Ok(()),
+        }
     }
 
-    fn decode(&mut self, buffer: &[u8]) -> Option<(usize, Self::Output)> {
-        Some((buffer.len(), serde_json::from_slice(buffer)))
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        use std::io::ErrorKind::*;
+        use std::io::Error;
+
+        // Nothing past the longest value allowed is looked at
+        let scanned = &buffer[..cmp::min(buffer.len(), self.max_value_len.saturating_add(1))];
+        let found = match self.framing {
+            Framing::Concatenated => value_len(scanned)?.map(|len| (0, len, len)),
+            Framing::Newline => line_len(scanned).map(|(start, end)| (start, end, end + 1)),
+        };
+
+        let (value, consumed) = match found {
+            Some((_, end, _)) if end > self.max_value_len => return Err(self.value_too_long()),
+            Some((start, end, consumed)) => (&buffer[start..end], consumed),
+            None if buffer.len() > self.max_value_len => return Err(self.value_too_long()),
+            None => return Ok(None),
+        };
+
+        match serde_json::from_slice(value) {
+            Ok(value) => Ok(Some((consumed, value))),
+            Err(e) => Err(Error::new(InvalidData, e)),
+        }
     }
 }
 
@@ -41,8 +201,6 @@ mod tests {
     use super::*;
     use traits::*;
     use ferrous::dsl::*;
-    use serde_json::{error};
-    use std::io::{Write};
 
     #[derive(Debug, Copy, Clone, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
     struct Point {
@@ -61,23 +219,100 @@ mod tests {
 
         let decode = <JsonCodec<_> as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
 
+        expect(&decode).to(be_ok());
+        let decode = decode.unwrap();
         expect(&decode).to(be_some());
-        let (_, decoded_output) = decode.unwrap();
-        expect(&decoded_output).to(be_ok());
 
-        let decoded_point = decoded_output.unwrap();
+        let (_, decoded_point) = decode.unwrap();
         expect(&point).to(equal(&decoded_point));
     }
 
     #[test]
     fn test_codec_err() {
         let mut codec = JsonCodec::new();
-        let json = String::from("{invalid").into_bytes();
+        let json = String::from("{invalid}").into_bytes();
 
         let decode = <JsonCodec<String> as Codec<Vec<u8>>>::decode(&mut codec, &json[..]);
-        expect(&decode).to(be_some());
+        expect(&decode).to(be_err());
+    }
+
+    #[test]
+    fn test_codec_concatenated() {
+        let mut codec = JsonCodec::new();
+        let json = br#"{"x":1,"y":2} {"x":3,"y":"}"#;
+
+        let decode = <JsonCodec<Point> as Codec<Vec<u8>>>::decode(&mut codec, &json[..]);
+        let (num, point) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&13));
+        expect(&point).to(equal(&Point { x: 1, y: 2 }));
+
+        // The second value is truncated in the middle of a string
+        let decode = <JsonCodec<Point> as Codec<Vec<u8>>>::decode(&mut codec, &json[num..]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_codec_concatenated_scalars() {
+        let mut codec = JsonCodec::new();
+        let json = br#" "a\"}" 12"#;
+
+        let decode = <JsonCodec<String> as Codec<Vec<u8>>>::decode(&mut codec, &json[..]);
+        let (num, string) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&7));
+        expect(&string).to(equal(&String::from("a\"}")));
+
+        // A number is not complete until something follows it
+        let decode = <JsonCodec<u32> as Codec<Vec<u8>>>::decode(&mut JsonCodec::new(), &json[num..]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_codec_newline_delimited() {
+        let mut codec = JsonCodec::newline_delimited();
+        let mut buffer = Vec::new();
+
+        expect(&codec.encode(&mut buffer, Point { x: 1, y: 2 })).to(be_ok());
+        expect(&buffer.last()).to(equal(&Some(&b'\n')));
+
+        let mut json = b"\r\n".to_vec();
+        json.extend_from_slice(&buffer[..]);
+        let decode = <JsonCodec<Point> as Codec<Vec<u8>>>::decode(&mut codec, &json[..]);
+        let (num, point) = decode.unwrap().unwrap();
+        expect(&num).to(equal(&json.len()));
+        expect(&point).to(equal(&Point { x: 1, y: 2 }));
+
+        let decode = <JsonCodec<Point> as Codec<Vec<u8>>>::decode(&mut codec, &json[..json.len() - 1]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_codec_max_value_len() {
+        let mut codec = JsonCodec::new();
+        codec.set_max_value_len(8);
+
+        let decode = <JsonCodec<String> as Codec<Vec<u8>>>::decode(&mut codec, br#" "abcde" "#);
+        expect(&decode.unwrap()).to(be_some());
+
+        let decode = <JsonCodec<String> as Codec<Vec<u8>>>::decode(&mut codec, br#""abcdefgh""#);
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+
+        // Still incomplete, but already too long
+        let decode = <JsonCodec<Point> as Codec<Vec<u8>>>::decode(&mut codec, br#"{"x":1,"y":"#);
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_codec_newline_delimited_max_value_len() {
+        let mut codec = JsonCodec::newline_delimited();
+        codec.set_max_value_len(8);
+
+        let decode = <JsonCodec<Vec<u32>> as Codec<Vec<u8>>>::decode(&mut codec, b"[1,2,3]\n");
+        expect(&decode.unwrap()).to(be_some());
+
+        let decode = <JsonCodec<Vec<u32>> as Codec<Vec<u8>>>::decode(&mut codec, b"[1,2,3,4,5]\n");
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
 
-        let (_, decoded_output) = decode.unwrap();
-        expect(&decoded_output).to(be_err());
+        let decode = <JsonCodec<Vec<u32>> as Codec<Vec<u8>>>::decode(&mut codec, b"[1,2,3,4,5");
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
     }
 }
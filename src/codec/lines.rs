@@ -0,0 +1,209 @@
+use std::io::{self, Write};
+use std::marker::PhantomData;
+
+use traits::*;
+
+/// Default maximum line length accepted by a `LinesCodec`, 64 KiB.
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Line ending written by `LinesCodec::encode`. Both are accepted when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    Lf,
+    CrLf,
+}
+
+impl Terminator {
+    fn as_bytes(&self) -> &'static [u8] {
+        match *self {
+            Terminator::Lf => b"\n",
+            Terminator::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// Types a line can be decoded into.
+pub trait Line: Sized {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self>;
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl Line for Vec<u8> {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self[..]
+    }
+}
+
+impl Line for String {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Splits a byte stream on `\n` or `\r\n`, yielding each line without its terminator.
+pub struct LinesCodec<T> {
+    terminator: Terminator,
+    max_line_len: usize,
+    line_type: PhantomData<fn() -> T>,
+}
+
+impl<T> LinesCodec<T> {
+    pub fn new() -> LinesCodec<T> {
+        LinesCodec {
+            terminator: Terminator::Lf,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            line_type: PhantomData,
+        }
+    }
+
+    /// Sets the line ending appended by `encode`. Defaults to `Terminator::Lf`.
+    pub fn set_terminator(&mut self, terminator: Terminator) {
+        self.terminator = terminator;
+    }
+
+    /// Sets the maximum line length, not counting the terminator. Longer lines fail to decode
+    /// instead of being buffered.
+    pub fn set_max_line_len(&mut self, len: usize) {
+        self.max_line_len = len;
+    }
+
+    fn line_too_long(&self) -> io::Error {
+        let msg = format!("line exceeds maximum length of {}", self.max_line_len);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+}
+
+impl<T> Default for LinesCodec<T> {
+    fn default() -> LinesCodec<T> {
+        LinesCodec::new()
+    }
+}
+
+impl<T: Line, B: Write> Codec<B> for LinesCodec<T> {
+    type Input = T;
+    type Output = T;
+
+    fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
+        buffer.write_all(input.as_bytes())?;
+        buffer.write_all(self.terminator.as_bytes())
+    }
+
+    /// Nothing is kept between calls, a partial line is searched for its end again once more of
+    /// it arrives. Lines are at most `max_line_len` long, which keeps that cheap.
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        let end = match buffer.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None => {
+                if buffer.len() > self.max_line_len + 1 {
+                    return Err(self.line_too_long())
+                }
+                return Ok(None)
+            },
+        };
+
+        let mut line = &buffer[..end];
+        if line.last() == Some(&b'\r') {
+            line = &line[..line.len() - 1];
+        }
+
+        if line.len() > self.max_line_len {
+            return Err(self.line_too_long())
+        }
+
+        let output = T::from_bytes(line)?;
+        Ok(Some((end + 1, output)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+
+    fn decode<T: Line>(codec: &mut LinesCodec<T>, buffer: &[u8]) -> io::Result<Option<(usize, T)>> {
+        <LinesCodec<T> as Codec<Vec<u8>>>::decode(codec, buffer)
+    }
+
+    #[test]
+    fn test_codec_decode_lines() {
+        let mut codec = LinesCodec::<String>::new();
+        let buffer = b"foo\r\nbar\nbaz";
+
+        let (num, line) = decode(&mut codec, &buffer[..]).unwrap().unwrap();
+        expect(&num).to(equal(&5));
+        expect(&line).to(equal(&String::from("foo")));
+
+        let (num, line) = decode(&mut codec, &buffer[5..]).unwrap().unwrap();
+        expect(&num).to(equal(&4));
+        expect(&line).to(equal(&String::from("bar")));
+
+        let decode = decode(&mut codec, &buffer[9..]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+
+    #[test]
+    fn test_codec_decode_partial_line() {
+        let mut codec = LinesCodec::<Vec<u8>>::new();
+        let buffer = b"hello\r\n";
+
+        expect(&decode(&mut codec, &buffer[..3]).unwrap()).to(be_none());
+        expect(&decode(&mut codec, &buffer[..6]).unwrap()).to(be_none());
+
+        let (num, line) = decode(&mut codec, &buffer[..]).unwrap().unwrap();
+        expect(&num).to(equal(&7));
+        expect(&line).to(equal(&b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_codec_decode_unrelated_buffers() {
+        let mut codec = LinesCodec::<Vec<u8>>::new();
+
+        // The partial line is not remembered, the next buffer starts a line of its own
+        expect(&decode(&mut codec, b"abc").unwrap()).to(be_none());
+        let (num, line) = decode(&mut codec, b"x\nyz").unwrap().unwrap();
+        expect(&num).to(equal(&2));
+        expect(&line).to(equal(&b"x".to_vec()));
+    }
+
+    #[test]
+    fn test_codec_max_line_len() {
+        let mut codec = LinesCodec::<String>::new();
+        codec.set_max_line_len(4);
+
+        expect(&decode(&mut codec, b"abcd\r\n").unwrap()).to(be_some());
+        expect(&decode(&mut codec, b"abcde\n")).to(be_err());
+        // No terminator in sight, but already too long
+        expect(&decode(&mut codec, b"abcdefg")).to(be_err());
+    }
+
+    #[test]
+    fn test_codec_invalid_utf8() {
+        let mut codec = LinesCodec::<String>::new();
+
+        let decode = decode(&mut codec, b"\xff\xfe\n");
+        expect(&decode).to(be_err());
+        expect(&decode.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_codec_encode_terminator() {
+        let mut codec = LinesCodec::<String>::new();
+        let mut buffer = Vec::new();
+
+        expect(&codec.encode(&mut buffer, String::from("foo"))).to(be_ok());
+        codec.set_terminator(Terminator::CrLf);
+        expect(&codec.encode(&mut buffer, String::from("bar"))).to(be_ok());
+
+        expect(&buffer).to(equal(&b"foo\nbar\r\n".to_vec()));
+    }
+}
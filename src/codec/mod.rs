@@ -1,2 +1,4 @@
 #[cfg(feature = "json_codec")] pub mod json;
 pub mod fixed_length;
+pub mod delimited;
+pub mod lines;
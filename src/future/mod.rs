@@ -1,9 +1,29 @@
 use std::io;
 use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
 use std::fmt;
 
+type Callback<T> = Box<dyn FnOnce(io::Result<T>) + Send>;
+
+struct State<T> {
+    data: Option<io::Result<T>>,
+    callback: Option<Callback<T>>,
+    complete: bool,
+    cancelled: bool,
+}
+
+impl<T> State<T> {
+    /// Takes the result of a completed future. Fails if it has already been taken.
+    fn take(&mut self) -> io::Result<T> {
+        match self.data.take() {
+            Some(data) => data,
+            None => Err(io::Error::other("future result already taken")),
+        }
+    }
+}
+
 struct Inner<T> {
-    data: Mutex<Option<io::Result<T>>>,
+    state: Mutex<State<T>>,
     cond: Condvar,
 }
 
@@ -17,30 +37,94 @@ impl<T: fmt::Debug> fmt::Debug for Inner<T> {
 impl<T> Inner<T> {
     fn new() -> Inner<T> {
         Inner{
-            data: Mutex::new(None),
+            state: Mutex::new(State {
+                data: None,
+                callback: None,
+                complete: false,
+                cancelled: false,
+            }),
             cond: Condvar::new(),
         }
     }
 
     fn get(&self) -> io::Result<T> {
-        let mut guard = self.data.lock().expect("lock poisoned");
-        while guard.is_none() {
+        let mut guard = self.state.lock().expect("lock poisoned");
+        while !guard.complete {
             guard = self.cond.wait(guard).expect("lock posioned while waiting");
         }
 
-        // We know this is a Some variant
-        guard.take().unwrap()
+        guard.take()
+    }
+
+    fn get_timeout(&self, timeout: Duration) -> Option<io::Result<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.state.lock().expect("lock poisoned");
+        while !guard.complete {
+            let now = Instant::now();
+            if now >= deadline {
+                return None
+            }
+
+            guard = self.cond.wait_timeout(guard, deadline - now).expect("lock posioned while waiting").0;
+        }
+
+        Some(guard.take())
     }
 
-    fn set(&self, data: io::Result<T>) {
-        let mut guard = self.data.lock().expect("lock poisoned");
-        *guard = Some(data);
-        self.cond.notify_one();
+    fn try_get(&self) -> Option<io::Result<T>> {
+        let mut guard = self.state.lock().expect("lock poisoned");
+        if guard.complete {
+            Some(guard.take())
+        } else {
+            None
+        }
+    }
+
+    fn set(&self, data: io::Result<T>) -> Result<(), io::Result<T>> {
+        let mut guard = self.state.lock().expect("lock poisoned");
+        if guard.complete {
+            return Err(data)
+        }
+
+        guard.complete = true;
+        match guard.callback.take() {
+            Some(callback) => {
+                // Never run user code while holding the lock
+                drop(guard);
+                callback(data);
+            },
+            None => {
+                guard.data = Some(data);
+                self.cond.notify_one();
+            },
+        }
+        Ok(())
+    }
+
+    fn on_complete(&self, callback: Callback<T>) {
+        let mut guard = self.state.lock().expect("lock poisoned");
+        if guard.complete {
+            let data = guard.take();
+            drop(guard);
+            callback(data);
+        } else {
+            guard.callback = Some(callback);
+        }
     }
 
     fn is_done(&self) -> bool {
-        let guard = self.data.lock().expect("lock poisoned");
-        guard.is_some()
+        let guard = self.state.lock().expect("lock poisoned");
+        guard.complete
+    }
+
+    fn cancel(&self) {
+        let mut guard = self.state.lock().expect("lock poisoned");
+        guard.cancelled = true;
+    }
+
+    fn is_cancelled(&self) -> bool {
+        let guard = self.state.lock().expect("lock poisoned");
+        guard.cancelled
     }
 }
 
@@ -50,13 +134,85 @@ pub struct Future<T> {
 }
 
 impl<T> Future<T> {
+    /// Blocks until the promise is set. This must not be called from the thread that sets the
+    /// promise, such as from a protocol running on the event loop, as it would wait forever.
+    /// Use `try_get` or `on_complete` there instead.
     pub fn get(&self) -> io::Result<T> {
         self.inner.get()
     }
 
+    /// Blocks until the promise is set or `timeout` elapses. Returns `None` on timeout.
+    pub fn get_timeout(&self, timeout: Duration) -> Option<io::Result<T>> {
+        self.inner.get_timeout(timeout)
+    }
+
+    /// Takes the result if the promise has been set, without blocking. The result can only be
+    /// taken once, afterwards `get`, `try_get` and `on_complete` fail instead of waiting.
+    pub fn try_get(&self) -> Option<io::Result<T>> {
+        self.inner.try_get()
+    }
+
+    /// Returns true once the promise has been set, even if the result has since been taken.
     pub fn is_done(&self) -> bool {
         self.inner.is_done()
     }
+
+    /// Tells the promise side that nobody is interested in the result anymore. A pipeline will
+    /// not encode a write whose future has been cancelled.
+    pub fn cancel(&self) {
+        self.inner.cancel()
+    }
+}
+
+impl<T: Send + 'static> Future<T> {
+    /// Runs `f` with the result once the promise is set, on the thread that sets it. If the
+    /// promise has already been set, `f` runs immediately.
+    pub fn on_complete<F>(self, f: F)
+    where F: FnOnce(io::Result<T>) + Send + 'static
+    {
+        self.inner.on_complete(Box::new(f))
+    }
+
+    /// Returns a future for the result of `f`, which is called with the result of this future.
+    pub fn then<U, F>(self, f: F) -> Future<U>
+    where U: Send + 'static,
+          F: FnOnce(io::Result<T>) -> io::Result<U> + Send + 'static
+    {
+        let (promise, future) = pair();
+        self.on_complete(move |res| {
+            let _ = promise.set(f(res));
+        });
+        future
+    }
+
+    /// Returns a future for the successful value of this future mapped by `f`. Errors are passed
+    /// through unchanged.
+    pub fn map<U, F>(self, f: F) -> Future<U>
+    where U: Send + 'static,
+          F: FnOnce(T) -> U + Send + 'static
+    {
+        self.then(move |res| res.map(f))
+    }
+
+    /// Chains another asynchronous operation, started with the successful value of this future.
+    /// Errors are passed through without calling `f`.
+    pub fn and_then<U, F>(self, f: F) -> Future<U>
+    where U: Send + 'static,
+          F: FnOnce(T) -> Future<U> + Send + 'static
+    {
+        let (promise, future) = pair();
+        self.on_complete(move |res| {
+            match res {
+                Ok(value) => f(value).on_complete(move |res| {
+                    let _ = promise.set(res);
+                }),
+                Err(e) => {
+                    let _ = promise.set(Err(e));
+                },
+            }
+        });
+        future
+    }
 }
 
 #[derive(Debug)]
@@ -65,9 +221,25 @@ pub struct Promise<T> {
 }
 
 impl<T> Promise<T> {
-    pub fn set(&self, data: io::Result<T>) {
+    /// Completes the future, running its completion callback if one is registered. A promise can
+    /// only be set once, later calls return the data without touching the future.
+    pub fn set(&self, data: io::Result<T>) -> Result<(), io::Result<T>> {
         self.inner.set(data)
     }
+
+    /// Returns true if the future has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// A promise dropped without being set fails its future with a `BrokenPipe` error, so that
+/// nothing waits on it forever.
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        // Fails if the promise has already been set, which is fine
+        let _ = self.inner.set(Err(io::Error::new(io::ErrorKind::BrokenPipe, "promise dropped without being set")));
+    }
 }
 
 pub fn pair<T>() -> (Promise<T>, Future<T>) {
@@ -77,18 +249,96 @@ pub fn pair<T>() -> (Promise<T>, Future<T>) {
     };
 
     let future = Future {
-        inner: inner,
+        inner,
     };
 
     (promise, future)
 }
 
+/// Returns a future for the values of all `futures`, in order. It fails with the first error any
+/// of them completes with.
+pub fn join_all<T: Send + 'static>(futures: Vec<Future<T>>) -> Future<Vec<T>> {
+    let (promise, future) = pair();
+    if futures.is_empty() {
+        let _ = promise.set(Ok(Vec::new()));
+        return future
+    }
+
+    let promise = Arc::new(promise);
+    let values = Arc::new(Mutex::new((0..futures.len()).map(|_| None).collect::<Vec<_>>()));
+    for (i, f) in futures.into_iter().enumerate() {
+        let promise = promise.clone();
+        let values = values.clone();
+        f.on_complete(move |res| {
+            match res {
+                Ok(value) => {
+                    let mut values = values.lock().expect("lock poisoned");
+                    values[i] = Some(value);
+                    if values.iter().all(|v| v.is_some()) {
+                        let values = values.drain(..).map(|v| v.unwrap()).collect();
+                        let _ = promise.set(Ok(values));
+                    }
+                },
+                Err(e) => {
+                    let _ = promise.set(Err(e));
+                },
+            }
+        });
+    }
+
+    future
+}
+
+/// Returns a future for the first of `futures` to succeed, along with its index. It only fails
+/// once all of them have failed, with the last error.
+pub fn select<T: Send + 'static>(futures: Vec<Future<T>>) -> Future<(usize, T)> {
+    let (promise, future) = pair();
+    let promise = Arc::new(promise);
+    let remaining = Arc::new(Mutex::new(futures.len()));
+    for (i, f) in futures.into_iter().enumerate() {
+        let promise = promise.clone();
+        let remaining = remaining.clone();
+        f.on_complete(move |res| {
+            match res {
+                Ok(value) => {
+                    let _ = promise.set(Ok((i, value)));
+                },
+                Err(e) => {
+                    let mut remaining = remaining.lock().expect("lock poisoned");
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        let _ = promise.set(Err(e));
+                    }
+                },
+            }
+        });
+    }
+
+    future
+}
+
+/// Returns a future for the result of whichever of `futures` completes first, successfully or
+/// not.
+pub fn race<T: Send + 'static>(futures: Vec<Future<T>>) -> Future<T> {
+    let (promise, future) = pair();
+    let promise = Arc::new(promise);
+    for f in futures {
+        let promise = promise.clone();
+        f.on_complete(move |res| {
+            let _ = promise.set(res);
+        });
+    }
+
+    future
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ferrous::dsl::*;
     use std::thread;
     use std::sync::mpsc::channel;
+    use std::time::Duration;
     use std::io::{self, ErrorKind};
 
     #[test]
@@ -96,7 +346,7 @@ mod tests {
         let (promise, future) = pair::<u8>();
 
         let handle = thread::spawn(move || {
-            promise.set(Ok(27u8));
+            promise.set(Ok(27u8)).unwrap();
         });
         handle.join().unwrap();
 
@@ -110,7 +360,7 @@ mod tests {
         let (promise, future) = pair::<u8>();
 
         let handle = thread::spawn(move || {
-            promise.set(Err(io::Error::new(ErrorKind::Other, "boom!")));
+            promise.set(Err(io::Error::other("boom!"))).unwrap();
         });
         handle.join().unwrap();
 
@@ -125,10 +375,234 @@ mod tests {
 
         expect(&future.is_done()).to(equal(&false));
         let handle = thread::spawn(move || {
-            promise.set(Ok(27u8));
+            promise.set(Ok(27u8)).unwrap();
         });
         handle.join().unwrap();
 
         expect(&future.is_done()).to(equal(&true));
     }
+
+    #[test]
+    fn test_future_try_get() {
+        let (promise, future) = pair::<u8>();
+
+        expect(&future.try_get()).to(be_none());
+        promise.set(Ok(27u8)).unwrap();
+
+        let res = future.try_get();
+        expect(&res).to(be_some());
+        expect(&res.unwrap().unwrap()).to(equal(&27));
+    }
+
+    #[test]
+    fn test_future_try_get_consumed() {
+        let (promise, future) = pair::<u8>();
+        promise.set(Ok(27u8)).unwrap();
+        expect(&future.try_get().unwrap().unwrap()).to(equal(&27));
+
+        // Still done, but the result is gone
+        expect(&future.is_done()).to(equal(&true));
+        expect(&future.try_get().unwrap()).to(be_err());
+        expect(&future.get_timeout(Duration::from_secs(5)).unwrap()).to(be_err());
+        expect(&future.get()).to(be_err());
+
+        let (tx, rx) = channel();
+        future.on_complete(move |res| tx.send(res.is_err()).unwrap());
+        expect(&rx.try_recv()).to(equal(&Ok(true)));
+    }
+
+    #[test]
+    fn test_future_on_complete() {
+        let (promise, future) = pair::<u8>();
+        let (tx, rx) = channel();
+
+        future.on_complete(move |res| tx.send(res.unwrap()).unwrap());
+        expect(&rx.try_recv()).to(be_err());
+
+        promise.set(Ok(27u8)).unwrap();
+        expect(&rx.try_recv()).to(equal(&Ok(27)));
+    }
+
+    #[test]
+    fn test_future_on_complete_already_set() {
+        let (promise, future) = pair::<u8>();
+        let (tx, rx) = channel();
+
+        promise.set(Ok(27u8)).unwrap();
+        future.on_complete(move |res| tx.send(res.unwrap()).unwrap());
+        expect(&rx.try_recv()).to(equal(&Ok(27)));
+    }
+
+    #[test]
+    fn test_future_map() {
+        let (promise, future) = pair::<u8>();
+        let future = future.map(|n| n as u32 * 2);
+
+        promise.set(Ok(27u8)).unwrap();
+        expect(&future.get().unwrap()).to(equal(&54));
+    }
+
+    #[test]
+    fn test_future_map_err() {
+        let (promise, future) = pair::<u8>();
+        let future = future.map(|n| n as u32 * 2);
+
+        promise.set(Err(io::Error::new(ErrorKind::BrokenPipe, "boom!"))).unwrap();
+        expect(&future.get().unwrap_err().kind()).to(equal(&ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_future_then() {
+        let (promise, future) = pair::<u8>();
+        let future = future.then(|res| {
+            match res {
+                Ok(_) => Ok(true),
+                Err(_) => Ok(false),
+            }
+        });
+
+        promise.set(Err(io::Error::other("boom!"))).unwrap();
+        expect(&future.get().unwrap()).to(equal(&false));
+    }
+
+    #[test]
+    fn test_future_and_then() {
+        let (first, future) = pair::<u8>();
+        let (second, next) = pair::<u32>();
+        let future = future.and_then(move |_| next);
+
+        first.set(Ok(1u8)).unwrap();
+        expect(&future.is_done()).to(equal(&false));
+
+        second.set(Ok(2u32)).unwrap();
+        expect(&future.get().unwrap()).to(equal(&2));
+    }
+
+    #[test]
+    fn test_future_get_timeout() {
+        let (promise, future) = pair::<u8>();
+
+        let res = future.get_timeout(Duration::from_millis(10));
+        expect(&res).to(be_none());
+
+        let handle = thread::spawn(move || {
+            promise.set(Ok(27u8)).unwrap();
+        });
+        let res = future.get_timeout(Duration::from_secs(5));
+        handle.join().unwrap();
+
+        expect(&res).to(be_some());
+        expect(&res.unwrap().unwrap()).to(equal(&27));
+    }
+
+    #[test]
+    fn test_future_promise_dropped() {
+        let (promise, future) = pair::<u8>();
+
+        let handle = thread::spawn(move || {
+            drop(promise);
+        });
+        handle.join().unwrap();
+
+        let res = future.get();
+        expect(&res).to(be_err());
+        expect(&res.unwrap_err().kind()).to(equal(&ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_future_promise_dropped_after_set() {
+        let (promise, future) = pair::<u8>();
+
+        promise.set(Ok(27u8)).unwrap();
+        drop(promise);
+
+        expect(&future.get().unwrap()).to(equal(&27));
+    }
+
+    #[test]
+    fn test_future_cancel() {
+        let (promise, future) = pair::<u8>();
+
+        expect(&promise.is_cancelled()).to(equal(&false));
+        future.cancel();
+        expect(&promise.is_cancelled()).to(equal(&true));
+    }
+
+    #[test]
+    fn test_promise_set_once() {
+        let (promise, future) = pair::<u8>();
+
+        expect(&promise.set(Ok(1u8))).to(be_ok());
+        let res = promise.set(Ok(2u8));
+        expect(&res).to(be_err());
+        expect(&res.unwrap_err().unwrap()).to(equal(&2));
+
+        expect(&future.get().unwrap()).to(equal(&1));
+    }
+
+    #[test]
+    fn test_join_all() {
+        let (first, f1) = pair::<u8>();
+        let (second, f2) = pair::<u8>();
+        let future = join_all(vec!(f1, f2));
+
+        second.set(Ok(2u8)).unwrap();
+        expect(&future.is_done()).to(equal(&false));
+
+        first.set(Ok(1u8)).unwrap();
+        expect(&future.get().unwrap()).to(equal(&vec!(1, 2)));
+    }
+
+    #[test]
+    fn test_join_all_err() {
+        let (first, f1) = pair::<u8>();
+        let (_second, f2) = pair::<u8>();
+        let future = join_all(vec!(f1, f2));
+
+        first.set(Err(io::Error::other("boom!"))).unwrap();
+        expect(&future.get().unwrap_err().kind()).to(equal(&ErrorKind::Other));
+    }
+
+    #[test]
+    fn test_join_all_empty() {
+        let future = join_all::<u8>(Vec::new());
+        expect(&future.get().unwrap()).to(equal(&vec!()));
+    }
+
+    #[test]
+    fn test_select() {
+        let (first, f1) = pair::<u8>();
+        let (second, f2) = pair::<u8>();
+        let (third, f3) = pair::<u8>();
+        let future = select(vec!(f1, f2, f3));
+
+        first.set(Err(io::Error::other("boom!"))).unwrap();
+        expect(&future.is_done()).to(equal(&false));
+
+        third.set(Ok(3u8)).unwrap();
+        second.set(Ok(2u8)).unwrap();
+        expect(&future.get().unwrap()).to(equal(&(2, 3)));
+    }
+
+    #[test]
+    fn test_select_all_err() {
+        let (first, f1) = pair::<u8>();
+        let (second, f2) = pair::<u8>();
+        let future = select(vec!(f1, f2));
+
+        first.set(Err(io::Error::other("boom!"))).unwrap();
+        second.set(Err(io::Error::new(ErrorKind::BrokenPipe, "boom!"))).unwrap();
+        expect(&future.get().unwrap_err().kind()).to(equal(&ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_race() {
+        let (first, f1) = pair::<u8>();
+        let (second, f2) = pair::<u8>();
+        let future = race(vec!(f1, f2));
+
+        second.set(Err(io::Error::other("boom!"))).unwrap();
+        first.set(Ok(1u8)).unwrap();
+        expect(&future.get().unwrap_err().kind()).to(equal(&ErrorKind::Other));
+    }
 }
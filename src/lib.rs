@@ -12,7 +12,9 @@ extern crate netbuf;
 extern crate void;
 extern crate byteorder;
 #[macro_use] extern crate log;
+#[cfg(unix)] extern crate libc;
 
+#[cfg(feature = "rustls")] extern crate rustls;
 #[cfg(feature = "json_codec")] extern crate serde;
 #[cfg(feature = "json_codec")] extern crate serde_json;
 
@@ -21,8 +23,13 @@ extern crate byteorder;
 pub mod traits;
 pub mod future;
 pub mod pipeline;
+pub mod reactor;
 pub mod transport;
 pub mod codec;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use reactor::serve;
 
 #[cfg(test)]
 mod test_helpers;
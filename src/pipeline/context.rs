@@ -1,19 +1,92 @@
 use future::{Future, Promise, pair};
+use pipeline::ResumeHandle;
+use transport::tls::TlsInfo;
 use traits::*;
+use std::{option, vec};
+use std::iter::Chain;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+type ScheduledWrite<W> = (W, Promise<()>);
 
 pub struct PipelineContext<W> {
-    to_write: Option<(W, Promise<()>)>,
+    // The first write is kept apart so the common single write case never allocates
+    to_write: Option<ScheduledWrite<W>>,
+    more_writes: Vec<ScheduledWrite<W>>,
+    closing: bool,
+    write_blocked: bool,
+    // Number of writes the protocol may still make in this stage
+    writes_left: usize,
+    read_paused: bool,
+    resume: Option<ResumeHandle>,
+    tls_info: Option<Arc<TlsInfo>>,
+    timeout: Option<Duration>,
 }
 
 impl<W> PipelineContext<W> {
     pub fn new() -> PipelineContext<W> {
         PipelineContext {
             to_write: None,
+            more_writes: Vec::new(),
+            closing: false,
+            write_blocked: false,
+            writes_left: usize::MAX,
+            read_paused: false,
+            resume: None,
+            tls_info: None,
+            timeout: None,
         }
     }
 
-    pub fn into(self) -> Option<(W, Promise<()>)> {
-        self.to_write
+    /// Sets the handle returned by `resume_handle`.
+    pub fn set_resume_handle(&mut self, handle: ResumeHandle) {
+        self.resume = Some(handle);
+    }
+
+    /// Sets what `tls_info` returns.
+    pub fn set_tls_info(&mut self, info: Option<Arc<TlsInfo>>) {
+        self.tls_info = info;
+    }
+
+    /// Returns the timeout the protocol set during this stage, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns true if reading should be paused once the current stage finishes.
+    pub fn is_read_paused(&self) -> bool {
+        self.read_paused
+    }
+
+    /// Sets the current read state, which the protocol may then change.
+    pub fn set_read_paused(&mut self, paused: bool) {
+        self.read_paused = paused;
+    }
+
+    /// Rejects every write made through this context.
+    pub fn set_write_blocked(&mut self, blocked: bool) {
+        self.write_blocked = blocked;
+    }
+
+    /// Limits the number of writes accepted by this context. Unlimited by default.
+    pub fn set_write_budget(&mut self, budget: usize) {
+        self.writes_left = budget;
+    }
+
+    /// Returns true if the protocol used up the write budget of this stage.
+    pub fn is_write_budget_spent(&self) -> bool {
+        self.writes_left == 0
+    }
+
+    /// Returns true if the protocol asked for the pipeline to be closed.
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Returns the scheduled writes, in the order they were made.
+    pub fn into(self) -> Chain<option::IntoIter<ScheduledWrite<W>>, vec::IntoIter<ScheduledWrite<W>>> {
+        self.to_write.into_iter().chain(self.more_writes)
     }
 }
 
@@ -21,39 +94,169 @@ impl<W> Context for PipelineContext<W> {
     type Write = W;
 
     fn write(&mut self, obj: Self::Write) -> Result<Future<()>, Self::Write> {
-        if self.to_write.is_some() {
+        if !self.is_writable() {
             return Err(obj)
         }
 
+        self.writes_left -= 1;
+        let (promise, future) = pair();
+        if self.to_write.is_none() {
+            self.to_write = Some((obj, promise));
+        } else {
+            self.more_writes.push((obj, promise));
+        }
+        Ok(future)
+    }
+
+    fn is_writable(&self) -> bool {
+        !self.write_blocked && self.writes_left > 0
+    }
+
+    fn pause_read(&mut self) {
+        self.read_paused = true;
+    }
+
+    fn resume_read(&mut self) {
+        self.read_paused = false;
+    }
+
+    /// Without a handle from the pipeline, the returned handle is not connected to anything.
+    fn resume_handle(&self) -> ResumeHandle {
+        self.resume.clone().unwrap_or_default()
+    }
+
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_deref()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    fn close(&mut self) {
+        self.closing = true;
+    }
+}
+
+/// Context handed to a `DatagramProtocol`.
+pub struct DatagramPipelineContext<W> {
+    to_write: Vec<(W, SocketAddr, Promise<()>)>,
+    closing: bool,
+}
+
+impl<W> DatagramPipelineContext<W> {
+    pub fn new() -> DatagramPipelineContext<W> {
+        DatagramPipelineContext {
+            to_write: Vec::new(),
+            closing: false,
+        }
+    }
+
+    /// Returns true if the protocol asked for the pipeline to be closed.
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Returns the scheduled datagrams, in the order they were written.
+    pub fn into(self) -> vec::IntoIter<(W, SocketAddr, Promise<()>)> {
+        self.to_write.into_iter()
+    }
+}
+
+impl<W> DatagramContext for DatagramPipelineContext<W> {
+    type Write = W;
+
+    fn write_to(&mut self, obj: Self::Write, addr: SocketAddr) -> Result<Future<()>, Self::Write> {
         let (promise, future) = pair();
-        self.to_write = Some((obj, promise));
+        self.to_write.push((obj, addr, promise));
         Ok(future)
     }
 
     fn close(&mut self) {
-        unimplemented!()
+        self.closing = true;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use traits::*;
     use ferrous::dsl::*;
 
     #[test]
     fn test_write() {
         let mut ctx = PipelineContext::new();
-        let write = 9u8;
-        let res = ctx.write(write);
+        let res = ctx.write(9u8);
         expect(&res).to(be_ok());
 
-        let res = ctx.write(write);
-        expect(&res).to(be_err());
-        let err = res.unwrap_err();
-        expect(&err).to(equal(&9u8));
+        let mut to_write = ctx.into();
+        expect(&to_write.next().map(|(w, _)| w)).to(equal(&Some(9u8)));
+        expect(&to_write.next()).to(be_none());
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let mut ctx = PipelineContext::new();
+        expect(&ctx.write(1u8)).to(be_ok());
+        expect(&ctx.write(2u8)).to(be_ok());
+        expect(&ctx.write(3u8)).to(be_ok());
+
+        let writes = ctx.into().map(|(w, _)| w).collect::<Vec<_>>();
+        expect(&writes).to(equal(&vec!(1, 2, 3)));
+    }
+
+    #[test]
+    fn test_write_blocked() {
+        let mut ctx = PipelineContext::new();
+        ctx.set_write_blocked(true);
+        expect(&ctx.is_writable()).to(equal(&false));
+
+        let res = ctx.write(9u8);
+        expect(&res.unwrap_err()).to(equal(&9u8));
+        expect(&ctx.into().next()).to(be_none());
+    }
+
+    #[test]
+    fn test_write_budget() {
+        let mut ctx = PipelineContext::new();
+        ctx.set_write_budget(2);
+        expect(&ctx.write(1u8)).to(be_ok());
+        expect(&ctx.is_write_budget_spent()).to(equal(&false));
+        expect(&ctx.write(2u8)).to(be_ok());
+
+        expect(&ctx.is_writable()).to(equal(&false));
+        expect(&ctx.is_write_budget_spent()).to(equal(&true));
+        expect(&ctx.write(3u8).unwrap_err()).to(equal(&3u8));
+        expect(&ctx.into().count()).to(equal(&2));
+    }
+
+    #[test]
+    fn test_pause_read() {
+        let mut ctx = PipelineContext::<u8>::new();
+        ctx.pause_read();
+        expect(&ctx.is_read_paused()).to(equal(&true));
+
+        ctx.resume_read();
+        expect(&ctx.is_read_paused()).to(equal(&false));
+    }
+
+    #[test]
+    fn test_close() {
+        let mut ctx = PipelineContext::<u8>::new();
+        expect(&ctx.is_closing()).to(equal(&false));
+
+        ctx.close();
+        expect(&ctx.is_closing()).to(equal(&true));
+    }
+
+    #[test]
+    fn test_datagram_write_to() {
+        let first = "127.0.0.1:1000".parse().unwrap();
+        let second = "127.0.0.1:2000".parse().unwrap();
+        let mut ctx = DatagramPipelineContext::new();
+        expect(&ctx.write_to(1u8, first)).to(be_ok());
+        expect(&ctx.write_to(2u8, second)).to(be_ok());
 
-        let to_write = ctx.into();
-        expect(&to_write).to(be_some());
+        let writes = ctx.into().map(|(w, addr, _)| (w, addr)).collect::<Vec<_>>();
+        expect(&writes).to(equal(&vec!((1, first), (2, second))));
     }
 }
@@ -0,0 +1,335 @@
+use pipeline::context::DatagramPipelineContext;
+use pipeline::DEFAULT_READ_BUDGET;
+use pipeline::writes::{fail_if_cancelled, fail_unsent};
+use future::Promise;
+use std::collections::VecDeque;
+use std::io::{self};
+use std::net::SocketAddr;
+use traits::*;
+
+/// A pipeline for message oriented transports. Every datagram received is decoded as a whole and
+/// handed to the protocol with its source address, and every write is sent as one datagram.
+///
+/// A datagram that fails to decode is dropped instead of closing the pipeline.
+pub struct DatagramPipeline<T, C, P> {
+    transport: T,
+    codec: C,
+    protocol: P,
+    closed: bool,
+    read_budget: usize,
+    read_pending: bool,
+    // Encoded datagrams the socket could not take yet, in order
+    unsent: VecDeque<(Vec<u8>, SocketAddr, Promise<()>)>,
+}
+
+impl<T, C, P> DatagramPipeline<T, C, P>
+where T: DatagramTransport,
+      C: Codec<Vec<u8>>,
+      P: DatagramProtocol<Input=C::Output, Output=C::Input>
+{
+    pub fn new(t: T, c: C, p: P) -> DatagramPipeline<T, C, P> {
+        DatagramPipeline {
+            transport: t,
+            codec: c,
+            protocol: p,
+            closed: false,
+            read_budget: DEFAULT_READ_BUDGET,
+            read_pending: false,
+            unsent: VecDeque::new(),
+        }
+    }
+
+    /// Sets the maximum number of datagrams received per `readable` call.
+    pub fn set_read_budget(&mut self, budget: usize) {
+        self.read_budget = budget;
+    }
+
+    /// Returns true if the last `readable` call used up its read budget.
+    pub fn is_read_pending(&self) -> bool {
+        self.read_pending
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns true once the pipeline has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Calls spawned method and then writable, unless the protocol closed the pipeline.
+    pub fn spawned(&mut self) {
+        let mut ctx = DatagramPipelineContext::new();
+        self.protocol.spawned(&mut ctx);
+        self.transport.spawned();
+
+        self.finish(ctx);
+        self.writable();
+    }
+
+    pub fn closed(&mut self) {
+        self.shutdown(None);
+    }
+
+    /// Closes the pipeline because of an io error, which is handed to both the protocol and the
+    /// transport.
+    pub fn failed(&mut self, err: &io::Error) {
+        self.shutdown(Some(err));
+    }
+
+    /// Notifies the protocol and transport of the close. Datagrams that were not sent yet fail.
+    fn shutdown(&mut self, err: Option<&io::Error>) {
+        if self.closed {
+            return
+        }
+
+        self.closed = true;
+        for (_, _, promise) in self.unsent.drain(..) {
+            fail_unsent(promise, err);
+        }
+
+        let mut ctx = DatagramPipelineContext::<P::Output>::new();
+        self.protocol.closed(&mut ctx, err);
+        self.transport.closed(err);
+    }
+
+    /// Sends the datagrams scheduled on the context, and then closes the pipeline if the protocol
+    /// asked for it.
+    fn finish(&mut self, ctx: DatagramPipelineContext<P::Output>) {
+        let close = ctx.is_closing();
+        for (to_write, addr, promise) in ctx.into() {
+            if fail_if_cancelled(&promise) {
+                continue
+            }
+
+            let mut datagram = Vec::new();
+            match self.codec.encode(&mut datagram, to_write) {
+                Ok(()) => self.unsent.push_back((datagram, addr, promise)),
+                Err(e) => {
+                    let _ = promise.set(Err(e));
+                },
+            }
+        }
+        self.flush();
+
+        if close {
+            self.shutdown(None);
+        }
+    }
+
+    /// Sends queued datagrams until the socket stops taking them. A datagram that fails to send
+    /// only fails its own write.
+    fn flush(&mut self) {
+        while let Some((datagram, addr, promise)) = self.unsent.pop_front() {
+            match self.transport.send_to(&datagram[..], &addr) {
+                Ok(true) => {
+                    let _ = promise.set(Ok(()));
+                },
+                Ok(false) => {
+                    self.unsent.push_front((datagram, addr, promise));
+                    return
+                },
+                Err(e) => {
+                    debug!("datagram pipeline: could not send to {}: {}", addr, e);
+                    let _ = promise.set(Err(e));
+                },
+            }
+        }
+    }
+
+    /// Handles a wakeup of whatever drives the pipeline by running the read pass left
+    /// outstanding, if any.
+    pub fn wakeup(&mut self) {
+        if self.read_pending {
+            self.readable();
+        }
+    }
+
+    /// Receives and handles datagrams until none are waiting, up to the read budget.
+    pub fn readable(&mut self) {
+        self.read_pending = false;
+        if self.closed {
+            return
+        }
+
+        for _ in 0..self.read_budget {
+            let (output, addr) = {
+                let (datagram, addr) = match self.transport.recv_from() {
+                    Ok(Some(received)) => received,
+                    Ok(None) => return,
+                    Err(ref e) => {
+                        self.failed(e);
+                        return
+                    },
+                };
+
+                match self.codec.decode(datagram) {
+                    Ok(Some((_, output))) => (output, addr),
+                    Ok(None) => {
+                        warn!("datagram pipeline: dropping incomplete datagram from {}", addr);
+                        continue
+                    },
+                    Err(e) => {
+                        warn!("datagram pipeline: dropping datagram from {}: {}", addr, e);
+                        continue
+                    },
+                }
+            };
+
+            let mut ctx = DatagramPipelineContext::new();
+            self.protocol.received_data(&mut ctx, output, addr);
+            self.finish(ctx);
+            if self.closed {
+                return
+            }
+        }
+
+        self.read_pending = true;
+    }
+
+    /// Signifies that the socket is now writable. Sends any queued datagrams and, once none are
+    /// left, calls the protocol's `writable` method.
+    pub fn writable(&mut self) {
+        if self.closed {
+            return
+        }
+
+        self.flush();
+        if !self.unsent.is_empty() {
+            return
+        }
+
+        let mut ctx = DatagramPipelineContext::new();
+        self.protocol.writable(&mut ctx);
+        self.finish(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use std::io;
+    use std::net::SocketAddr;
+    use test_helpers::{FakeDatagramTransport, DatagramAssertions, FakeCodec, DatagramEchoProtocol};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_datagram_pipeline_echo() {
+        let assertions = DatagramAssertions::new();
+        {
+            let mut a = assertions.lock().unwrap();
+            a.inbound.push_back((vec!(1, 2), addr(1000)));
+            a.inbound.push_back((vec!(3), addr(2000)));
+        }
+        let transport = FakeDatagramTransport::new(assertions.clone());
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::new(), DatagramEchoProtocol::new());
+        pipeline.spawned();
+        pipeline.readable();
+        expect(&pipeline.is_read_pending()).to(equal(&false));
+
+        let a = assertions.lock().unwrap();
+        expect(&a.spawned).to(equal(&true));
+        expect(&a.sent).to(equal(&vec!((vec!(1, 2), addr(1000)), (vec!(3), addr(2000)))));
+    }
+
+    #[test]
+    fn test_datagram_pipeline_wakeup() {
+        let assertions = DatagramAssertions::new();
+        {
+            let mut a = assertions.lock().unwrap();
+            a.inbound.push_back((vec!(1), addr(1000)));
+            a.inbound.push_back((vec!(2), addr(1000)));
+        }
+        let transport = FakeDatagramTransport::new(assertions.clone());
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::new(), DatagramEchoProtocol::new());
+        pipeline.set_read_budget(1);
+        pipeline.readable();
+        expect(&pipeline.is_read_pending()).to(equal(&true));
+        expect(&assertions.lock().unwrap().sent.len()).to(equal(&1));
+
+        pipeline.wakeup();
+        expect(&assertions.lock().unwrap().sent.len()).to(equal(&2));
+    }
+
+    #[test]
+    fn test_datagram_pipeline_blocked_send() {
+        let assertions = DatagramAssertions::new();
+        {
+            let mut a = assertions.lock().unwrap();
+            a.inbound.push_back((vec!(1), addr(1000)));
+            a.send_blocked = true;
+        }
+        let transport = FakeDatagramTransport::new(assertions.clone());
+        let protocol = DatagramEchoProtocol::new();
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::new(), protocol.clone());
+        pipeline.readable();
+        let future = protocol.take_future().unwrap();
+        expect(&future.is_done()).to(equal(&false));
+
+        assertions.lock().unwrap().send_blocked = false;
+        pipeline.writable();
+        expect(&future.get()).to(be_ok());
+        expect(&assertions.lock().unwrap().sent).to(equal(&vec!((vec!(1), addr(1000)))));
+    }
+
+    #[test]
+    fn test_datagram_pipeline_drops_bad_datagram() {
+        let assertions = DatagramAssertions::new();
+        {
+            let mut a = assertions.lock().unwrap();
+            a.inbound.push_back((vec!(1), addr(1000)));
+            a.inbound.push_back((vec!(2, 2), addr(2000)));
+        }
+        let transport = FakeDatagramTransport::new(assertions.clone());
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::with_frame_len(2), DatagramEchoProtocol::new());
+        pipeline.readable();
+        expect(&pipeline.is_closed()).to(equal(&false));
+
+        let a = assertions.lock().unwrap();
+        expect(&a.sent).to(equal(&vec!((vec!(2, 2), addr(2000)))));
+    }
+
+    #[test]
+    fn test_datagram_pipeline_send_error() {
+        let assertions = DatagramAssertions::new();
+        {
+            let mut a = assertions.lock().unwrap();
+            a.inbound.push_back((vec!(1), addr(1000)));
+            a.send_error = Some(io::ErrorKind::PermissionDenied);
+        }
+        let transport = FakeDatagramTransport::new(assertions.clone());
+        let protocol = DatagramEchoProtocol::new();
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::new(), protocol.clone());
+        pipeline.readable();
+        expect(&pipeline.is_closed()).to(equal(&false));
+
+        let res = protocol.take_future().unwrap().get();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::PermissionDenied));
+    }
+
+    #[test]
+    fn test_datagram_pipeline_recv_error() {
+        let assertions = DatagramAssertions::new();
+        assertions.lock().unwrap().recv_error = Some(io::ErrorKind::Other);
+        let transport = FakeDatagramTransport::new(assertions.clone());
+
+        let mut pipeline = DatagramPipeline::new(transport, FakeCodec::new(), DatagramEchoProtocol::new());
+        pipeline.readable();
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let a = assertions.lock().unwrap();
+        expect(&a.closed).to(equal(&true));
+        expect(&a.error_kind).to(equal(&Some(io::ErrorKind::Other)));
+    }
+}
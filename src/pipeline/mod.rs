@@ -3,5 +3,13 @@
 //! Pipelines are the main unit of composition in Nexus.
 
 mod context;
+mod writes;
+#[allow(clippy::module_inception)]
 mod pipeline;
-pub use self::pipeline::{Pipeline};
+pub use self::pipeline::{Pipeline, DEFAULT_READ_BUDGET};
+
+mod resume;
+pub use self::resume::ResumeHandle;
+
+mod datagram;
+pub use self::datagram::DatagramPipeline;
@@ -1,13 +1,53 @@
 use pipeline::context::PipelineContext;
-use future::{Promise};
-use void::Void;
+use pipeline::ResumeHandle;
+use pipeline::writes::{fail_if_cancelled, fail_unsent};
+use future::Promise;
+use std::collections::VecDeque;
 use std::io::{self};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use traits::*;
+use transport::tls::TlsInfo;
+
+/// Default number of messages decoded per `readable` call.
+pub const DEFAULT_READ_BUDGET: usize = 32;
 
 pub struct Pipeline<T, C, P> {
     transport: T,
     codec: C,
     protocol: P,
+    closed: bool,
+    // Set once the protocol asked for a close, until the transport has flushed its writes
+    closing: bool,
+    // How long a close may wait for writes to be flushed, and when that runs out
+    linger: Option<Duration>,
+    linger_deadline: Option<Instant>,
+    // Set once the peer hung up, the pipeline closes as soon as nothing is left to do
+    hung_up: bool,
+    read_budget: usize,
+    read_pending: bool,
+    read_paused: bool,
+    // Shared with protocols that want to resume reading from elsewhere
+    resume: ResumeHandle,
+    // Looked up from the transport until its handshake is done, then shared with every context
+    tls_info: Option<Arc<TlsInfo>>,
+    write_budget: usize,
+    // Set when the protocol used up its write budget, until `writable` is called again
+    write_pending: bool,
+    idle_timeout: Option<Duration>,
+    idle_deadline: Option<Instant>,
+    // Deadline of the timeout set by the protocol
+    protocol_deadline: Option<Instant>,
+    low_watermark: usize,
+    high_watermark: usize,
+    // Set once the write buffer reaches the high watermark, until it drains to the low one
+    write_blocked: bool,
+    // Set when a flush outside of `writable` unblocked writes, so the protocol is still told
+    write_unblocked: bool,
+    // Total number of bytes ever encoded into the transport
+    written: u64,
+    // Writes waiting to be flushed, with the value of `written` once each was encoded
+    pending_writes: VecDeque<(u64, Promise<()>)>,
 }
 
 impl<T, C, P> Pipeline<T, C, P>
@@ -20,8 +60,151 @@ where T: Transport,
             transport: t,
             codec: c,
             protocol: p,
+            closed: false,
+            closing: false,
+            linger: None,
+            linger_deadline: None,
+            hung_up: false,
+            read_budget: DEFAULT_READ_BUDGET,
+            read_pending: false,
+            read_paused: false,
+            resume: ResumeHandle::new(),
+            tls_info: None,
+            write_budget: usize::MAX,
+            write_pending: false,
+            idle_timeout: None,
+            idle_deadline: None,
+            protocol_deadline: None,
+            low_watermark: usize::MAX,
+            high_watermark: usize::MAX,
+            write_blocked: false,
+            write_unblocked: false,
+            written: 0,
+            pending_writes: VecDeque::new(),
         }
     }
+
+    /// Sets the maximum number of messages decoded per `readable` call, so that a single busy
+    /// connection cannot starve the others sharing its event loop.
+    pub fn set_read_budget(&mut self, budget: usize) {
+        self.read_budget = budget;
+    }
+
+    /// Sets the maximum number of writes a protocol may make per callback. Once it is used up,
+    /// `Context::is_writable` returns false and the protocol's `writable` method is called again
+    /// after the writes have been encoded, unless that fills the write buffer past its high
+    /// watermark. Unlimited by default.
+    pub fn set_write_budget(&mut self, budget: usize) {
+        self.write_budget = budget;
+    }
+
+    /// Returns true if the protocol used up its write budget and is waiting for another
+    /// `writable` call. The caller should call `writable` without waiting for the socket.
+    pub fn is_write_pending(&self) -> bool {
+        self.write_pending
+    }
+
+    /// Returns true while the protocol has paused reading.
+    pub fn is_read_paused(&self) -> bool {
+        self.read_paused
+    }
+
+    /// Sets the function a `ResumeHandle` calls to get `wakeup` called, on whichever thread the
+    /// pipeline runs on.
+    pub fn set_waker<F>(&mut self, wake: F)
+    where F: Fn() + Send + 'static
+    {
+        self.resume.set_waker(wake);
+    }
+
+    /// Sets how long the pipeline may go without receiving a complete message before it is
+    /// closed with a `TimedOut` error. There is no idle timeout by default.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+        self.reset_idle_deadline();
+    }
+
+    /// Sets how long a close requested by the protocol may wait for the write buffer to be
+    /// flushed. Once it runs out the pipeline is closed with a `TimedOut` error, failing the
+    /// writes that are left. A close waits as long as it takes by default.
+    pub fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = linger;
+    }
+
+    /// Returns true while a close requested by the protocol waits for the write buffer to be
+    /// flushed. Nothing more is read in the meantime.
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Returns the earliest instant `timeout` should be called at, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.closed {
+            return None
+        }
+        if self.closing {
+            return self.linger_deadline
+        }
+
+        match (self.idle_deadline, self.protocol_deadline) {
+            (Some(idle), Some(protocol)) => Some(idle.min(protocol)),
+            (idle, protocol) => idle.or(protocol),
+        }
+    }
+
+    fn reset_idle_deadline(&mut self) {
+        self.idle_deadline = self.idle_timeout.map(|t| Instant::now() + t);
+    }
+
+    /// Sets the write buffer watermarks, in bytes. Once the transport has `high` or more bytes
+    /// waiting to be flushed, writes are rejected until it drains to `low` and the protocol's
+    /// `writable` callback is called. The buffer is unbounded by default.
+    ///
+    /// Panics if `low` is greater than `high`.
+    pub fn set_write_watermarks(&mut self, low: usize, high: usize) {
+        assert!(low <= high, "low watermark must not exceed the high watermark");
+        self.low_watermark = low;
+        self.high_watermark = high;
+    }
+
+    /// Returns true while writes are rejected because the write buffer is above its high
+    /// watermark.
+    pub fn is_write_blocked(&self) -> bool {
+        self.write_blocked
+    }
+
+    /// Returns true if the last `readable` call used up its read budget, meaning more messages
+    /// may already be buffered. The caller should call `readable` again without waiting for the
+    /// socket to become readable.
+    pub fn is_read_pending(&self) -> bool {
+        self.read_pending
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Returns a reference to the protocol.
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    /// Returns a mutable reference to the protocol.
+    pub fn protocol_mut(&mut self) -> &mut P {
+        &mut self.protocol
+    }
+
+    /// Returns true once the pipeline has been closed, either explicitly or because of an io
+    /// error.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
 }
 
 impl<T, C, P> Pipeline<T, C, P>
@@ -29,71 +212,309 @@ where T: Transport,
       C: Codec<T::Buffer>,
       P: Protocol<Input=C::Output, Output=C::Input>
 {
-    /// Calls spawned method and then writable.
+    fn context(&mut self) -> PipelineContext<P::Output> {
+        if self.tls_info.is_none() {
+            self.tls_info = self.transport.tls_info().map(Arc::new);
+        }
+
+        let mut ctx = PipelineContext::new();
+        ctx.set_write_blocked(self.write_blocked);
+        ctx.set_write_budget(self.write_budget);
+        ctx.set_read_paused(self.read_paused);
+        ctx.set_resume_handle(self.resume.clone());
+        ctx.set_tls_info(self.tls_info.clone());
+        ctx
+    }
+
+    /// Applies a pause or resume and any timeout requested by the protocol. Resuming schedules a
+    /// read pass for anything buffered while reading was paused.
+    fn update_state(&mut self, ctx: &PipelineContext<P::Output>) {
+        if let Some(timeout) = ctx.timeout() {
+            self.protocol_deadline = Some(Instant::now() + timeout);
+        }
+
+        if self.read_paused && !ctx.is_read_paused() {
+            self.read_pending = true;
+        }
+        self.read_paused = ctx.is_read_paused();
+    }
+
+    /// Calls spawned method and then writable, unless the protocol closed the pipeline.
     pub fn spawned(&mut self) {
-        let mut ctx = PipelineContext::<P::Output>::new();
+        let mut ctx = self.context();
         self.protocol.spawned(&mut ctx);
         self.transport.spawned();
-        self.writable();
+        self.update_state(&ctx);
+        self.reset_idle_deadline();
+
+        if ctx.is_closing() {
+            self.shutdown(None);
+        } else {
+            self.writable();
+        }
     }
 
     pub fn closed(&mut self) {
+        self.shutdown(None);
+    }
+
+    /// Signifies that the peer hung up. Messages that already arrived are still decoded, and
+    /// the pipeline only closes once they have been handled and every reply has been flushed.
+    pub fn hangup(&mut self) {
+        self.hung_up = true;
+        self.close_if_drained();
+    }
+
+    /// Finishes a close requested by the protocol, or a hangup, once the transport has nothing
+    /// left to flush and the protocol is not waiting to write more. After a hangup, any
+    /// outstanding read pass has to be done first too.
+    fn close_if_drained(&mut self) {
+        if self.closed || self.write_pending || self.transport.buffered() > 0 {
+            return
+        }
+
+        if self.closing || (self.hung_up && !self.read_pending && !self.read_paused) {
+            self.shutdown(None);
+        }
+    }
+
+    /// Closes the pipeline because of an io error, which is handed to both the protocol and the
+    /// transport.
+    pub fn failed(&mut self, err: &io::Error) {
+        self.shutdown(Some(err));
+    }
+
+    /// Notifies the protocol and transport of the close. Both are only ever notified once. Writes
+    /// that have not been flushed yet fail.
+    fn shutdown(&mut self, err: Option<&io::Error>) {
+        if self.closed {
+            return
+        }
+
+        self.closed = true;
+        for (_, promise) in self.pending_writes.drain(..) {
+            fail_unsent(promise, err);
+        }
+
         let mut ctx = PipelineContext::<P::Output>::new();
-        self.protocol.closed(&mut ctx, None);
-        self.transport.closed(None);
+        self.protocol.closed(&mut ctx, err);
+        self.transport.closed(err);
+    }
+
+    /// Encodes the writes scheduled on the context, in order, and then closes the pipeline if
+    /// the protocol asked for it.
+    fn finish(&mut self, ctx: PipelineContext<P::Output>) {
+        self.update_state(&ctx);
+        let close = ctx.is_closing();
+        let budget_spent = ctx.is_write_budget_spent();
+        for (to_write, promise) in ctx.into() {
+            if !fail_if_cancelled(&promise) {
+                self.encode(to_write, promise);
+            }
+        }
+
+        // The protocol wants to write more. Unless the buffer is full, which has it called once
+        // the buffer drains, or it is closing anyway, it gets another `writable` call.
+        if budget_spent && !self.write_blocked && !close {
+            self.write_pending = true;
+        }
+
+        if close {
+            // The transport is only closed once everything written so far has been flushed
+            self.closing = true;
+            self.linger_deadline = self.linger.map(|t| Instant::now() + t);
+            self.flush();
+            self.close_if_drained();
+        }
+    }
+
+    /// Encodes `to_write` into the transport. The promise is kept until its bytes are flushed.
+    fn encode(&mut self, to_write: P::Output, promise: Promise<()>) {
+        let before = self.transport.buffered();
+        let res = self.codec.encode(self.transport.buffer(), to_write);
+        // Count anything a failed encode left behind too, it still has to be flushed
+        self.written += self.transport.buffered().saturating_sub(before) as u64;
+
+        if self.transport.buffered() >= self.high_watermark {
+            self.write_blocked = true;
+        }
+
+        match res {
+            Ok(()) => self.pending_writes.push_back((self.written, promise)),
+            Err(e) => {
+                let _ = promise.set(Err(e));
+            },
+        }
+    }
+
+    /// Flushes the transport and resolves the futures of every write that has fully left it.
+    fn flush(&mut self) {
+        if let Err(e) = self.transport.writable() {
+            self.failed(&e);
+            return
+        }
+
+        let flushed = self.written.saturating_sub(self.transport.buffered() as u64);
+        while let Some(&(mark, _)) = self.pending_writes.front() {
+            if mark > flushed {
+                break
+            }
+
+            let (_, promise) = self.pending_writes.pop_front().unwrap();
+            let _ = promise.set(Ok(()));
+        }
+
+        if self.write_blocked && self.transport.buffered() <= self.low_watermark {
+            self.write_blocked = false;
+            self.write_unblocked = true;
+        }
     }
 
-    fn read_data(&mut self) -> io::Result<Option<(C::Input, Promise<()>)>> {
+    /// Calls the protocol's `writable` method if a flush made while reading or handling a
+    /// timeout unblocked writes. The socket will not necessarily report writability again.
+    fn notify_unblocked(&mut self) {
+        if self.write_unblocked && !self.closed {
+            self.writable();
+        }
+    }
+
+    fn read_data(&mut self) -> io::Result<Option<PipelineContext<P::Output>>> {
         let (num, output) = {
-            let read = try!(self.transport.read());
-            let decoded = self.codec.decode(read);
+            let read = self.transport.read()?;
+            let decoded = self.codec.decode(read)?;
             match decoded {
                 Some(d) => d,
                 None => return Ok(None),
             }
         };
         self.transport.consume(num);
+        self.reset_idle_deadline();
 
-        let mut ctx = PipelineContext::new();
+        let mut ctx = self.context();
         self.protocol.received_data(&mut ctx, output);
 
-        Ok(ctx.into())
+        Ok(Some(ctx))
     }
 
+    /// Decodes and handles every complete message available from the transport, up to the read
+    /// budget or until the protocol pauses reading. Replies are flushed together once decoding
+    /// stops.
     pub fn readable(&mut self) {
-        match self.read_data() {
-            Ok(opt) => {
-                opt.map(|(to_write, promise)| {
-                    promise.set(self.codec.encode(self.transport.buffer(),
-                                                  to_write));
-                });
-            },
-            Err(ref e) => {
-                let mut ctx = PipelineContext::<P::Output>::new();
-                self.protocol.closed(&mut ctx, Some(e));
-                self.transport.closed(Some(e));
+        self.read_pending = false;
+        if self.closed || self.closing || self.read_paused {
+            return
+        }
+
+        let mut decoded = 0;
+        while decoded < self.read_budget {
+            match self.read_data() {
+                Ok(Some(ctx)) => {
+                    self.finish(ctx);
+                    if self.closed || self.closing {
+                        return
+                    }
+                    decoded += 1;
+                    if self.read_paused {
+                        break
+                    }
+                },
+                Ok(None) => break,
+                Err(ref e) => {
+                    self.failed(e);
+                    return
+                },
             }
         }
+
+        self.read_pending = decoded == self.read_budget && !self.read_paused;
+        if self.transport.is_peer_closed() {
+            self.hung_up = true;
+        }
+        self.flush();
+        self.notify_unblocked();
+        self.close_if_drained();
     }
 
-    /// Signifies that the socket is now writable. This will call transport and
-    /// protocol 'writable' method and write any data generated.
-    pub fn writable(&mut self) {
-        let protocol = &mut self.protocol;
-        let codec = &mut self.codec;
-        let transport = &mut self.transport;
+    /// Handles a wakeup of whatever drives the pipeline. A resume requested through a
+    /// `ResumeHandle` is applied, then the read and write passes left outstanding are run.
+    /// Wakeups may be spurious, in which case nothing happens.
+    pub fn wakeup(&mut self) {
+        if self.resume.take_request() && self.read_paused && !self.closed {
+            debug!("pipeline: resuming read");
+            self.read_paused = false;
+            self.read_pending = true;
+        }
 
-        let mut ctx = PipelineContext::new();
-        protocol.writable(&mut ctx);
+        if self.read_pending {
+            self.readable();
+        }
+        if self.write_pending {
+            self.writable();
+        }
+    }
 
-        match ctx.into() {
-            Some((to_write, promise)) => {
-                promise.set(codec.encode(transport.buffer(), to_write));
-            },
-            None => {}
+    /// Handles every deadline that has passed by `now`. An expired idle timeout or linger closes
+    /// the pipeline, an expired protocol timeout calls the protocol's `timeout` method.
+    pub fn timeout(&mut self, now: Instant) {
+        if self.closed {
+            return
         }
 
-        transport.writable();
+        if self.closing {
+            if self.linger_deadline.is_some_and(|d| d <= now) {
+                let err = io::Error::new(io::ErrorKind::TimedOut, "close timed out before writes were flushed");
+                self.failed(&err);
+            }
+            return
+        }
+
+        if self.idle_deadline.is_some_and(|d| d <= now) {
+            let err = io::Error::new(io::ErrorKind::TimedOut, "connection idle");
+            self.failed(&err);
+            return
+        }
+
+        if self.protocol_deadline.is_some_and(|d| d <= now) {
+            self.protocol_deadline = None;
+            let mut ctx = self.context();
+            self.protocol.timeout(&mut ctx);
+
+            self.finish(ctx);
+            if !self.closed {
+                self.flush();
+            }
+            self.notify_unblocked();
+            self.close_if_drained();
+        }
+    }
+
+    /// Signifies that the socket is now writable. This flushes the transport and, unless the
+    /// write buffer is still above its low watermark, calls the protocol's `writable` method and
+    /// writes any data generated.
+    pub fn writable(&mut self) {
+        self.write_pending = false;
+        if self.closed {
+            return
+        }
+
+        self.flush();
+        // The protocol is told below, or not at all while the buffer is still full
+        self.write_unblocked = false;
+        if self.closed || self.closing || self.write_blocked {
+            // Once unblocked the protocol is called anyway
+            self.write_pending = false;
+            self.close_if_drained();
+            return
+        }
+
+        let mut ctx = self.context();
+        self.protocol.writable(&mut ctx);
+
+        self.finish(ctx);
+        if !self.closed {
+            self.flush();
+        }
+        self.close_if_drained();
     }
 }
 
@@ -101,8 +522,10 @@ where T: Transport,
 mod tests {
     use super::*;
     use ferrous::dsl::*;
-    use std::io::{self, Write, Read};
+    use std::io::{self, Write};
     use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
     use test_helpers::{FakeTransport, TransportAssertions, FakeCodec, FakeProtocol};
 
     fn load_protocol_output(proto: &Arc<Mutex<FakeProtocol>>, out: Vec<u8>) {
@@ -128,7 +551,7 @@ mod tests {
         expect(&(p.future)).to(be_some());
         expect(&(p.future.take().unwrap().get())).to(be_ok());
 
-        let mut t = assertions.lock().unwrap();
+        let t = assertions.lock().unwrap();
         expect(&(t.writable)).to(equal(&true));
     }
 
@@ -150,7 +573,7 @@ mod tests {
         expect(&(p.future)).to(be_some());
         expect(&(p.future.take().unwrap().get())).to(be_ok());
 
-        let mut t = assertions.lock().unwrap();
+        let t = assertions.lock().unwrap();
         expect(&(t.spawned)).to(equal(&true));
     }
 
@@ -166,11 +589,12 @@ mod tests {
         load_protocol_output(&protocol, vec!(3,3,3));
 
         pipeline.closed();
+        expect(&pipeline.is_closed()).to(equal(&true));
 
-        let mut p = protocol.lock().unwrap();
+        let p = protocol.lock().unwrap();
         expect(&(p.closed)).to(equal(&true));
 
-        let mut t = assertions.lock().unwrap();
+        let t = assertions.lock().unwrap();
         expect(&(t.closed)).to(equal(&true));
     }
 
@@ -201,6 +625,30 @@ mod tests {
         expect(&(p.future.take().unwrap().get())).to(be_ok());
     }
 
+    #[test]
+    fn test_pipeline_cancelled_write() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let codec = FakeCodec::new();
+
+            let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+            load_protocol_output(&protocol, vec!(3,3,3));
+            protocol.lock().unwrap().cancel = true;
+
+            pipeline.readable();
+        }
+
+        // Input consumed, but nothing written
+        expect(&vec.len()).to(equal(&0));
+
+        let mut p = protocol.lock().unwrap();
+        let res = p.future.take().unwrap().get();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::Interrupted));
+    }
+
     #[test]
     fn test_pipeline_read_io_error() {
         let mut vec = vec!(1, 1, 1);
@@ -218,11 +666,551 @@ mod tests {
         let expected = vec!(1,1,1);
         expect(&expected).to(equal(&vec));
 
-        let mut p = protocol.lock().unwrap();
+        let p = protocol.lock().unwrap();
         expect(&(p.closed)).to(equal(&true));
 
         let mut t = assertions.lock().unwrap();
         expect(&(t.closed)).to(equal(&true));
         expect(&(t.error_kind.take().unwrap())).to(equal(&io::ErrorKind::Other));
     }
+
+    #[test]
+    fn test_pipeline_decode_error() {
+        let mut vec = vec!(1, 1, 1);
+        let assertions = TransportAssertions::new();
+        let protocol = FakeProtocol::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let codec = FakeCodec::failing();
+
+            let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+
+            pipeline.readable();
+            expect(&pipeline.is_closed()).to(equal(&true));
+        }
+
+        let p = protocol.lock().unwrap();
+        expect(&(p.closed)).to(equal(&true));
+        expect(&(p.error_kind)).to(equal(&Some(io::ErrorKind::InvalidData)));
+
+        let t = assertions.lock().unwrap();
+        expect(&(t.closed)).to(equal(&true));
+        expect(&(t.error_kind)).to(equal(&Some(io::ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn test_pipeline_close_after_write() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let codec = FakeCodec::new();
+
+            let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+            load_protocol_output(&protocol, vec!(3,3,3));
+            protocol.lock().unwrap().close = true;
+
+            pipeline.readable();
+            expect(&pipeline.is_closed()).to(equal(&true));
+
+            // Already closed, neither side should be notified again
+            pipeline.closed();
+            pipeline.readable();
+        }
+
+        // The final write still makes it to the transport
+        let expected = vec!(3,3,3);
+        expect(&expected).to(equal(&vec));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.closed_count)).to(equal(&1));
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+
+        let t = assertions.lock().unwrap();
+        expect(&(t.writable)).to(equal(&true));
+        expect(&(t.closed)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_pipeline_close_waits_for_flush() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3,3,3));
+        protocol.lock().unwrap().close = true;
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        expect(&pipeline.is_closing()).to(equal(&true));
+        expect(&pipeline.is_closed()).to(equal(&false));
+        expect(&(assertions.lock().unwrap().closed)).to(equal(&false));
+
+        assertions.lock().unwrap().write_blocked = false;
+        pipeline.writable();
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.error_kind)).to(be_none());
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+        expect(&(assertions.lock().unwrap().error_kind)).to(be_none());
+    }
+
+    #[test]
+    fn test_pipeline_close_linger() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_linger(Some(Duration::from_secs(10)));
+        load_protocol_output(&protocol, vec!(3,3,3));
+        protocol.lock().unwrap().close = true;
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        let deadline = pipeline.next_deadline().unwrap();
+        pipeline.timeout(deadline);
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.error_kind)).to(equal(&Some(io::ErrorKind::TimedOut)));
+        let res = p.future.take().unwrap().get();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn test_pipeline_close_on_spawn() {
+        let mut vec = vec!(1, 1, 1);
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+        let protocol = FakeProtocol::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        protocol.lock().unwrap().close = true;
+
+        pipeline.spawned();
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let p = protocol.lock().unwrap();
+        expect(&(p.closed_count)).to(equal(&1));
+        // Protocol is not asked to write once it has closed
+        expect(&(p.future)).to(be_none());
+
+        let t = assertions.lock().unwrap();
+        expect(&(t.closed)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_pipeline_readable_decodes_all_frames() {
+        let mut vec = vec!(1, 1, 2, 2, 3, 3, 4);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let codec = FakeCodec::with_frame_len(2);
+
+            let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+            load_protocol_output(&protocol, vec!(9));
+
+            pipeline.readable();
+            expect(&pipeline.is_read_pending()).to(equal(&false));
+        }
+
+        // The trailing partial frame is left in the transport, followed by one reply per frame
+        let expected = vec!(4, 9, 9, 9);
+        expect(&expected).to(equal(&vec));
+
+        let p = protocol.lock().unwrap();
+        expect(&(p.input)).to(equal(&vec!(1, 1, 2, 2, 3, 3)));
+    }
+
+    #[test]
+    fn test_pipeline_read_budget() {
+        let mut vec = vec!(1, 1, 2, 2, 3, 3);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(2);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_read_budget(2);
+
+        pipeline.readable();
+        expect(&pipeline.is_read_pending()).to(equal(&true));
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1, 1, 2, 2)));
+
+        pipeline.readable();
+        expect(&pipeline.is_read_pending()).to(equal(&false));
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1, 1, 2, 2, 3, 3)));
+    }
+
+    #[test]
+    fn test_pipeline_hangup_decodes_buffered_frames() {
+        let mut vec = vec!(1, 1, 2, 2, 3, 3);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(2);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_read_budget(2);
+
+        pipeline.readable();
+        pipeline.hangup();
+        expect(&pipeline.is_closed()).to(equal(&false));
+
+        // The last frame is still handled before the pipeline closes
+        pipeline.readable();
+        expect(&pipeline.is_closed()).to(equal(&true));
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1, 1, 2, 2, 3, 3)));
+    }
+
+    #[test]
+    fn test_pipeline_hangup_flushes_replies() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        pipeline.hangup();
+        expect(&pipeline.is_closed()).to(equal(&false));
+
+        assertions.lock().unwrap().write_blocked = false;
+        pipeline.writable();
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.error_kind)).to(be_none());
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+    }
+
+    #[test]
+    fn test_pipeline_write_resolves_on_flush() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        let future = protocol.lock().unwrap().future.take().unwrap();
+        expect(&future.is_done()).to(equal(&false));
+
+        assertions.lock().unwrap().write_blocked = false;
+        pipeline.writable();
+        expect(&future.get()).to(be_ok());
+    }
+
+    #[test]
+    fn test_pipeline_write_error() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_error = Some(io::ErrorKind::ConnectionReset);
+
+        pipeline.readable();
+        expect(&pipeline.is_closed()).to(equal(&true));
+
+        let mut p = protocol.lock().unwrap();
+        let res = p.future.take().unwrap().get();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::ConnectionReset));
+        expect(&(p.error_kind)).to(equal(&Some(io::ErrorKind::ConnectionReset)));
+    }
+
+    #[test]
+    fn test_pipeline_close_fails_unflushed_write() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        pipeline.closed();
+
+        let res = protocol.lock().unwrap().future.take().unwrap().get();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_pipeline_write_watermarks() {
+        let mut vec = vec!(1, 2);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(1);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_write_watermarks(2, 3);
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_blocked = true;
+
+        // The first reply fills the buffer up to the high watermark, the second is rejected
+        pipeline.readable();
+        expect(&pipeline.is_write_blocked()).to(equal(&true));
+        expect(&(protocol.lock().unwrap().rejected)).to(equal(&1));
+        let future = protocol.lock().unwrap().future.take().unwrap();
+
+        // Still blocked, so the protocol is not asked to write
+        pipeline.writable();
+        expect(&(protocol.lock().unwrap().future)).to(be_none());
+
+        assertions.lock().unwrap().write_blocked = false;
+        pipeline.writable();
+        expect(&pipeline.is_write_blocked()).to(equal(&false));
+        expect(&future.get()).to(be_ok());
+        expect(&(protocol.lock().unwrap().future)).to(be_some());
+    }
+
+    #[test]
+    fn test_pipeline_write_unblocked_while_reading() {
+        let mut vec = vec!(1, 2);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(1);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_write_watermarks(2, 3);
+        pipeline.set_read_budget(1);
+        load_protocol_output(&protocol, vec!(3,3,3));
+        assertions.lock().unwrap().write_blocked = true;
+
+        pipeline.readable();
+        expect(&pipeline.is_write_blocked()).to(equal(&true));
+        let future = protocol.lock().unwrap().future.take().unwrap();
+
+        // The buffer drains during the next read pass, the reply to that message is rejected
+        // but the protocol is told it can write again
+        assertions.lock().unwrap().write_blocked = false;
+        pipeline.readable();
+        expect(&pipeline.is_write_blocked()).to(equal(&false));
+        expect(&future.get()).to(be_ok());
+
+        let p = protocol.lock().unwrap();
+        expect(&(p.rejected)).to(equal(&1));
+        expect(&(p.future)).to(be_some());
+    }
+
+    #[test]
+    fn test_pipeline_write_batch() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let codec = FakeCodec::new();
+
+            let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+            load_protocol_output(&protocol, vec!(3,3,3));
+            protocol.lock().unwrap().batch = 2;
+
+            pipeline.readable();
+        }
+
+        // Both replies to the single message are written
+        let expected = vec!(3,3,3,3,3,3);
+        expect(&expected).to(equal(&vec));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+    }
+
+    #[test]
+    fn test_pipeline_write_budget() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_write_budget(2);
+        load_protocol_output(&protocol, vec!(3));
+        protocol.lock().unwrap().batch = 3;
+
+        pipeline.readable();
+        expect(&pipeline.is_write_pending()).to(equal(&true));
+        expect(&(protocol.lock().unwrap().rejected)).to(equal(&1));
+
+        // The protocol gets another go once the first writes are encoded
+        pipeline.writable();
+        expect(&(protocol.lock().unwrap().rejected)).to(equal(&2));
+        expect(&pipeline.is_write_pending()).to(equal(&true));
+    }
+
+    #[test]
+    fn test_pipeline_write_budget_unlimited() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        load_protocol_output(&protocol, vec!(3));
+        protocol.lock().unwrap().batch = 100;
+
+        pipeline.readable();
+        expect(&pipeline.is_write_pending()).to(equal(&false));
+        expect(&(protocol.lock().unwrap().rejected)).to(equal(&0));
+    }
+
+    #[test]
+    fn test_pipeline_write_budget_blocked() {
+        let mut vec = vec!(1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_write_budget(2);
+        pipeline.set_write_watermarks(0, 2);
+        load_protocol_output(&protocol, vec!(3));
+        protocol.lock().unwrap().batch = 3;
+        assertions.lock().unwrap().write_blocked = true;
+
+        // Above the high watermark, so the protocol waits for the buffer to drain instead
+        pipeline.readable();
+        expect(&pipeline.is_write_pending()).to(equal(&false));
+        pipeline.writable();
+        expect(&pipeline.is_write_blocked()).to(equal(&true));
+        expect(&pipeline.is_write_pending()).to(equal(&false));
+        expect(&(protocol.lock().unwrap().rejected)).to(equal(&1));
+    }
+
+    #[test]
+    fn test_pipeline_pause_read() {
+        let mut vec = vec!(1, 2);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(1);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        protocol.lock().unwrap().pause_read = true;
+
+        pipeline.readable();
+        expect(&pipeline.is_read_paused()).to(equal(&true));
+        expect(&pipeline.is_read_pending()).to(equal(&false));
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1)));
+
+        // Nothing more is decoded while paused
+        pipeline.readable();
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1)));
+
+        protocol.lock().unwrap().pause_read = false;
+        pipeline.writable();
+        expect(&pipeline.is_read_paused()).to(equal(&false));
+        expect(&pipeline.is_read_pending()).to(equal(&true));
+
+        pipeline.readable();
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1, 2)));
+    }
+
+    #[test]
+    fn test_pipeline_resume_handle() {
+        let mut vec = vec!(1, 2);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(1);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        protocol.lock().unwrap().pause_read = true;
+
+        pipeline.readable();
+        expect(&pipeline.is_read_paused()).to(equal(&true));
+        let handle = protocol.lock().unwrap().resume_handle.take().unwrap();
+
+        // Nothing happens without a request
+        pipeline.wakeup();
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1)));
+
+        protocol.lock().unwrap().pause_read = false;
+        thread::spawn(move || handle.resume()).join().unwrap();
+        pipeline.wakeup();
+        expect(&pipeline.is_read_paused()).to(equal(&false));
+        expect(&(protocol.lock().unwrap().input)).to(equal(&vec!(1, 2)));
+    }
+
+    #[test]
+    fn test_pipeline_protocol_timeout() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::new();
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        protocol.lock().unwrap().timeout = Some(Duration::from_secs(10));
+
+        pipeline.spawned();
+        let deadline = pipeline.next_deadline().unwrap();
+
+        pipeline.timeout(Instant::now());
+        expect(&(protocol.lock().unwrap().timed_out)).to(equal(&0));
+
+        protocol.lock().unwrap().future = None;
+        pipeline.timeout(deadline);
+        expect(&pipeline.next_deadline()).to(be_none());
+
+        let mut p = protocol.lock().unwrap();
+        expect(&(p.timed_out)).to(equal(&1));
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+    }
+
+    #[test]
+    fn test_pipeline_idle_timeout() {
+        let mut vec = vec!(1, 1, 1);
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let codec = FakeCodec::with_frame_len(1);
+
+        let mut pipeline = Pipeline::new(transport, codec, protocol.clone());
+        pipeline.set_idle_timeout(Some(Duration::from_secs(10)));
+        pipeline.set_read_budget(1);
+        let first = pipeline.next_deadline().unwrap();
+
+        // Receiving a message pushes the deadline back
+        pipeline.readable();
+        let second = pipeline.next_deadline().unwrap();
+        expect(&(second >= first)).to(equal(&true));
+
+        pipeline.timeout(second + Duration::from_secs(1));
+        expect(&pipeline.is_closed()).to(equal(&true));
+        expect(&pipeline.next_deadline()).to(be_none());
+
+        let p = protocol.lock().unwrap();
+        expect(&(p.error_kind)).to(equal(&Some(io::ErrorKind::TimedOut)));
+    }
 }
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+type Waker = Box<dyn Fn() + Send>;
+
+struct Inner {
+    requested: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Resumes reading on a paused pipeline from outside of the protocol's callbacks, such as from
+/// the thread a slow backend finished on. Handles are obtained through
+/// `Context::resume_handle` and can be cloned and sent between threads.
+#[derive(Clone)]
+pub struct ResumeHandle {
+    inner: Arc<Inner>,
+}
+
+impl ResumeHandle {
+    pub fn new() -> ResumeHandle {
+        ResumeHandle {
+            inner: Arc::new(Inner {
+                requested: AtomicBool::new(false),
+                waker: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Asks the pipeline to resume reading and wakes up whatever drives it. Has no effect if
+    /// reading is not paused by the time the pipeline handles the request.
+    pub fn resume(&self) {
+        self.inner.requested.store(true, Ordering::SeqCst);
+        let waker = self.inner.waker.lock().expect("lock poisoned");
+        if let Some(ref wake) = *waker {
+            wake();
+        }
+    }
+
+    /// Returns true if `resume` was called since the pipeline last handled a request.
+    pub fn is_resume_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    /// Sets the function `resume` calls to get the pipeline's `wakeup` method called.
+    pub fn set_waker<F>(&self, wake: F)
+    where F: Fn() + Send + 'static
+    {
+        *self.inner.waker.lock().expect("lock poisoned") = Some(Box::new(wake));
+    }
+
+    /// Returns and clears the pending request.
+    pub fn take_request(&self) -> bool {
+        self.inner.requested.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Default for ResumeHandle {
+    fn default() -> ResumeHandle {
+        ResumeHandle::new()
+    }
+}
+
+impl fmt::Debug for ResumeHandle {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.debug_struct("ResumeHandle")
+            .field("requested", &self.is_resume_requested())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    #[test]
+    fn test_resume_handle() {
+        let handle = ResumeHandle::new();
+        let (tx, rx) = channel();
+        handle.set_waker(move || tx.send(()).unwrap());
+
+        let remote = handle.clone();
+        thread::spawn(move || remote.resume()).join().unwrap();
+        expect(&rx.try_recv()).to(be_ok());
+
+        expect(&handle.take_request()).to(equal(&true));
+        expect(&handle.take_request()).to(equal(&false));
+    }
+}
@@ -0,0 +1,50 @@
+use future::Promise;
+use std::io;
+
+/// Fails the promise of a write the protocol cancelled before it was encoded. Returns true if it
+/// was cancelled, in which case the write should be dropped.
+pub fn fail_if_cancelled(promise: &Promise<()>) -> bool {
+    if !promise.is_cancelled() {
+        return false
+    }
+
+    let _ = promise.set(Err(io::Error::new(io::ErrorKind::Interrupted, "write cancelled")));
+    true
+}
+
+/// Fails the promise of a write that was still unsent when the pipeline closed, with a copy of
+/// the error the pipeline closed because of, if any.
+pub fn fail_unsent(promise: Promise<()>, err: Option<&io::Error>) {
+    let e = match err {
+        Some(e) => io::Error::new(e.kind(), e.to_string()),
+        None => io::Error::new(io::ErrorKind::BrokenPipe, "pipeline closed before write was sent"),
+    };
+    let _ = promise.set(Err(e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use future::pair;
+
+    #[test]
+    fn test_fail_if_cancelled() {
+        let (promise, future) = pair::<()>();
+        expect(&fail_if_cancelled(&promise)).to(equal(&false));
+
+        future.cancel();
+        expect(&fail_if_cancelled(&promise)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_fail_unsent() {
+        let (promise, future) = pair::<()>();
+        fail_unsent(promise, None);
+        expect(&future.get().unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+
+        let (promise, future) = pair::<()>();
+        fail_unsent(promise, Some(&io::Error::new(io::ErrorKind::ConnectionReset, "reset")));
+        expect(&future.get().unwrap_err().kind()).to(equal(&io::ErrorKind::ConnectionReset));
+    }
+}
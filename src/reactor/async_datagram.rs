@@ -0,0 +1,133 @@
+use rotor::{Response, Scope, GenericScope, Machine, EventSet, Void};
+use rotor::void::unreachable;
+use reactor::wakeup::{register, schedule_pass};
+use reactor::Socket;
+use pipeline::DatagramPipeline;
+use traits::*;
+use std::marker::PhantomData;
+
+/// A rotor state machine that owns a `DatagramPipeline` and forwards socket readiness to it.
+///
+/// The machine finishes once the pipeline is closed, which drops the transport.
+pub struct AsyncDatagramTransport<X, T, C, P> {
+    pipeline: DatagramPipeline<T, C, P>,
+    context: PhantomData<fn() -> X>,
+}
+
+impl<X, T, C, P> AsyncDatagramTransport<X, T, C, P>
+where T: DatagramTransport + Socket,
+      C: Codec<Vec<u8>>,
+      P: DatagramProtocol<Input=C::Output, Output=C::Input>
+{
+    /// Registers the transport's socket with the event loop and spawns the pipeline.
+    pub fn new<S>(pipeline: DatagramPipeline<T, C, P>, scope: &mut S) -> Response<Self, Void>
+    where S: GenericScope
+    {
+        let interest = EventSet::readable() | EventSet::writable();
+        if let Err(res) = register(scope, pipeline.transport().evented(), interest) {
+            return res
+        }
+
+        let mut machine = AsyncDatagramTransport {
+            pipeline,
+            context: PhantomData,
+        };
+        machine.pipeline.spawned();
+        machine.response(scope)
+    }
+
+    fn response<S, N>(self, scope: &mut S) -> Response<Self, N>
+    where S: GenericScope
+    {
+        if self.pipeline.is_closed() {
+            return Response::done()
+        }
+
+        schedule_pass(scope, self.pipeline.is_read_pending());
+        Response::ok(self)
+    }
+}
+
+impl<X, T, C, P> Machine for AsyncDatagramTransport<X, T, C, P>
+where T: DatagramTransport + Socket,
+      C: Codec<Vec<u8>>,
+      P: DatagramProtocol<Input=C::Output, Output=C::Input>
+{
+    type Context = X;
+    type Seed = Void;
+
+    fn create(seed: Self::Seed, _scope: &mut Scope<Self::Context>) -> Response<Self, Void> {
+        unreachable(seed)
+    }
+
+    fn ready(mut self, events: EventSet, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        if events.is_readable() {
+            self.pipeline.readable();
+        }
+
+        if events.is_writable() {
+            self.pipeline.writable();
+        }
+
+        if events.is_error() && !self.pipeline.is_closed() {
+            if let Err(e) = self.pipeline.transport().take_socket_error() {
+                self.pipeline.failed(&e);
+            }
+        }
+
+        self.response(scope)
+    }
+
+    fn spawned(self, _scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        Response::ok(self)
+    }
+
+    fn timeout(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        self.response(scope)
+    }
+
+    fn wakeup(mut self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        self.pipeline.wakeup();
+        self.response(scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use std::net::UdpSocket as StdUdpSocket;
+    use std::thread;
+    use std::time::Duration;
+    use transport::udp::UdpSocket;
+    use test_helpers::{FakeCodec, DatagramEchoProtocol};
+
+    #[test]
+    fn test_async_datagram_echo() {
+        let socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let pipeline = DatagramPipeline::new(socket, FakeCodec::new(), DatagramEchoProtocol::new());
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncDatagramTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let client = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.send_to(b"hello", addr).unwrap();
+        client.send_to(b"world", addr).unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, from) = client.recv_from(&mut buf).unwrap();
+        expect(&from).to(equal(&addr));
+        expect(&&buf[..len]).to(equal(&&b"hello"[..]));
+
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        expect(&&buf[..len]).to(equal(&&b"world"[..]));
+    }
+}
@@ -1,32 +1,494 @@
-use rotor::{Response, Scope, Machine, EventSet, PollOpt};
+use rotor::{Response, Scope, GenericScope, Machine, EventSet, PollOpt, Evented, Void, Time};
+use rotor::void::unreachable;
+use reactor::wakeup::{register, schedule_pass, waker};
+use pipeline::Pipeline;
+use traits::*;
+use std::io;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
-pub struct AsyncTransport<S> {
-    socket: S,
+/// A transport whose underlying socket can be registered with the event loop.
+pub trait Socket {
+    type Evented: Evented;
+
+    /// Returns the object that readiness events are delivered for.
+    fn evented(&self) -> &Self::Evented;
+
+    /// Returns and clears any pending error on the socket.
+    fn take_socket_error(&self) -> io::Result<()>;
 }
 
-impl<S> Machine for AsyncTransport<S> {
-    type Context = ();
-    type Seed = Void;
+enum Status {
+    /// Waiting for a non-blocking connect to finish, with an optional deadline.
+    Connecting(Option<Time>),
+    Established,
+}
+
+/// A rotor state machine that owns a `Pipeline` and forwards socket readiness to it.
+///
+/// The machine finishes once the pipeline is closed, which drops the transport.
+pub struct AsyncTransport<X, T, C, P> {
+    pipeline: Pipeline<T, C, P>,
+    status: Status,
+    // Events the socket is currently registered for
+    interest: EventSet,
+    context: PhantomData<fn() -> X>,
+}
+
+impl<X, T, C, P> AsyncTransport<X, T, C, P>
+where T: Transport + Socket,
+      C: Codec<T::Buffer>,
+      P: Protocol<Input=C::Output, Output=C::Input>
+{
+    /// Registers the transport's socket with the event loop and spawns the pipeline.
+    pub fn new<S>(mut pipeline: Pipeline<T, C, P>, scope: &mut S) -> Response<Self, Void>
+    where S: GenericScope
+    {
+        let interest = EventSet::readable() | EventSet::hup() | EventSet::error();
+        if let Err(res) = register(scope, pipeline.transport().evented(), interest) {
+            return res
+        }
 
-    fn create(void: Self::Seed, scope: &mut Scope<Self::Context>) -> Result<Self, Box<Error>> {
-        unreachable(void);
+        pipeline.set_waker(waker(scope));
+        let mut machine = AsyncTransport {
+            pipeline,
+            status: Status::Established,
+            interest,
+            context: PhantomData,
+        };
+        machine.pipeline.spawned();
+        machine.response(scope)
     }
 
-    fn ready(self, events: EventSet, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+    /// Registers a transport whose socket is still connecting. The pipeline is only spawned once
+    /// the connection is established. If it fails, or does not complete within `timeout`, the
+    /// protocol is closed with the error instead.
+    pub fn connect<S>(mut pipeline: Pipeline<T, C, P>,
+                      timeout: Option<Duration>,
+                      scope: &mut S) -> Response<Self, Void>
+    where S: GenericScope
+    {
+        if let Err(res) = register(scope, pipeline.transport().evented(), EventSet::all()) {
+            return res
+        }
+
+        pipeline.set_waker(waker(scope));
+        let machine = AsyncTransport {
+            pipeline,
+            status: Status::Connecting(timeout.map(|t| scope.now() + t)),
+            interest: EventSet::all(),
+            context: PhantomData,
+        };
+        machine.response(scope)
     }
 
-    fn spawned(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+    /// Finishes a pending connect. Returns the events that still need handling by the pipeline.
+    fn establish(&mut self, mut events: EventSet) -> EventSet {
+        if !(events.is_writable() || events.is_hup() || events.is_error()) {
+            return EventSet::none()
+        }
+
+        if let Err(e) = self.pipeline.transport().take_socket_error() {
+            debug!("async transport: connect failed: {}", e);
+            self.pipeline.failed(&e);
+            return EventSet::none()
+        }
+
+        debug!("async transport: connection established");
+        self.status = Status::Established;
+        self.pipeline.spawned();
+        // Spawning the pipeline already handled writability
+        events.remove(EventSet::writable());
+        events
     }
 
-    fn timeout(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+    /// Writability only matters while connecting or while the transport has data it could not
+    /// flush yet. Readability is dropped while the protocol has paused reading, and while a
+    /// close waits for the write buffer to drain.
+    fn wanted_interest(&self) -> EventSet {
+        match self.status {
+            Status::Connecting(_) => EventSet::all(),
+            Status::Established => {
+                let mut interest = EventSet::hup() | EventSet::error();
+                if !self.pipeline.is_read_paused() && !self.pipeline.is_closing() {
+                    interest.insert(EventSet::readable());
+                }
+                if self.pipeline.transport().buffered() > 0 {
+                    interest.insert(EventSet::writable());
+                }
+                interest
+            },
+        }
     }
 
-    fn wakeup(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+    fn update_interest<S>(&mut self, scope: &mut S)
+    where S: GenericScope
+    {
+        let interest = self.wanted_interest();
+        if interest == self.interest {
+            return
+        }
+
+        match scope.reregister(self.pipeline.transport().evented(), interest, PollOpt::edge()) {
+            Ok(()) => self.interest = interest,
+            Err(e) => {
+                error!("async transport: could not reregister socket: {}", e);
+                self.pipeline.failed(&e);
+            },
+        }
     }
 
-    // fn spawn_error(self, _scope: &mut Scope<Self::Context>, error: Box<Error>) -> Option<Self> {}
+    fn response<S, N>(mut self, scope: &mut S) -> Response<Self, N>
+    where S: GenericScope
+    {
+        if !self.pipeline.is_closed() {
+            self.update_interest(scope);
+            let pending = self.pipeline.is_read_pending() || self.pipeline.is_write_pending();
+            schedule_pass(scope, pending);
+        }
+
+        if self.pipeline.is_closed() {
+            return Response::done()
+        }
+
+        match self.status {
+            Status::Connecting(Some(deadline)) => Response::ok(self).deadline(deadline),
+            Status::Connecting(None) => Response::ok(self),
+            Status::Established => {
+                match self.pipeline.next_deadline() {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        let deadline = scope.now() + remaining;
+                        Response::ok(self).deadline(deadline)
+                    },
+                    None => Response::ok(self),
+                }
+            },
+        }
+    }
+}
+
+impl<X, T, C, P> Machine for AsyncTransport<X, T, C, P>
+where T: Transport + Socket,
+      C: Codec<T::Buffer>,
+      P: Protocol<Input=C::Output, Output=C::Input>
+{
+    type Context = X;
+    type Seed = Void;
+
+    fn create(seed: Self::Seed, _scope: &mut Scope<Self::Context>) -> Response<Self, Void> {
+        unreachable(seed)
+    }
+
+    fn ready(mut self, mut events: EventSet, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        if let Status::Connecting(_) = self.status {
+            events = self.establish(events);
+        }
+
+        // Read before handling a hangup so that data sent right before the peer closed the
+        // connection is still delivered to the protocol. Whatever the read budget left over is
+        // decoded through wakeups before the pipeline closes.
+        if events.is_readable() && !self.pipeline.is_closed() {
+            self.pipeline.readable();
+        }
+
+        if events.is_writable() && !self.pipeline.is_closed() {
+            self.pipeline.writable();
+        }
+
+        if (events.is_hup() || events.is_error()) && !self.pipeline.is_closed() {
+            match self.pipeline.transport().take_socket_error() {
+                Err(e) => self.pipeline.failed(&e),
+                Ok(()) => self.pipeline.hangup(),
+            }
+        }
+
+        self.response(scope)
+    }
+
+    fn spawned(self, _scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        Response::ok(self)
+    }
+
+    fn timeout(mut self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        match self.status {
+            Status::Connecting(Some(deadline)) => {
+                if scope.now() >= deadline {
+                    let err = io::Error::new(io::ErrorKind::TimedOut, "connect timed out");
+                    self.pipeline.failed(&err);
+                }
+            },
+            Status::Connecting(None) => {},
+            Status::Established => self.pipeline.timeout(Instant::now()),
+        }
+
+        self.response(scope)
+    }
+
+    fn wakeup(mut self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        self.pipeline.wakeup();
+        self.response(scope)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use rotor::mio::tcp::TcpStream as MioTcpStream;
+    use std::net::{SocketAddr, TcpListener, Shutdown};
+    use std::io::{self, Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use transport::tcp::TcpStream;
+    use test_helpers::{FakeCodec, FakeProtocol, EchoProtocol, SendProtocol};
+
+    fn run_connect(addr: SocketAddr, protocol: Arc<Mutex<FakeProtocol>>) {
+        thread::spawn(move || {
+            let stream = TcpStream::connect(&addr).unwrap();
+            let pipeline = Pipeline::new(stream, FakeCodec::new(), protocol);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::connect(pipeline, Some(Duration::from_secs(5)), scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+    }
+
+    fn wait_for<F>(protocol: &Arc<Mutex<FakeProtocol>>, f: F) -> bool
+    where F: Fn(&FakeProtocol) -> bool
+    {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if f(&protocol.lock().unwrap()) {
+                return true
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_async_transport_echo() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        thread::spawn(move || {
+            let pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::new(), EchoProtocol);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(b"hello").unwrap();
+
+        let mut buf = [0u8; 5];
+        let res = conn.read_exact(&mut buf);
+        expect(&res).to(be_ok());
+        expect(&&buf[..]).to(equal(&&b"hello"[..]));
+    }
+
+    #[test]
+    fn test_async_transport_read_budget() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        thread::spawn(move || {
+            let mut pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::with_frame_len(1), EchoProtocol);
+            pipeline.set_read_budget(1);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(b"hello").unwrap();
+
+        // Every frame is echoed even though only one is decoded per pass
+        let mut buf = [0u8; 5];
+        let res = conn.read_exact(&mut buf);
+        expect(&res).to(be_ok());
+        expect(&&buf[..]).to(equal(&&b"hello"[..]));
+    }
+
+    #[test]
+    fn test_async_transport_half_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        thread::spawn(move || {
+            let mut pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::with_frame_len(1), EchoProtocol);
+            pipeline.set_read_budget(4);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let msg = (0..100).collect::<Vec<u8>>();
+        conn.write_all(&msg[..]).unwrap();
+        conn.shutdown(Shutdown::Write).unwrap();
+
+        // Every frame sent before the hangup is echoed, then the connection is closed
+        let mut buf = Vec::new();
+        let res = conn.read_to_end(&mut buf);
+        expect(&res).to(be_ok());
+        expect(&buf).to(equal(&msg));
+    }
+
+    #[test]
+    fn test_async_transport_large_write() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        thread::spawn(move || {
+            let pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::new(), EchoProtocol);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        // Far more than the socket accepts at once, so the echo has to be flushed in parts
+        let msg = (0..4 * 1024 * 1024).map(|i| i as u8).collect::<Vec<u8>>();
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut writer = conn.try_clone().unwrap();
+        let to_send = msg.clone();
+        thread::spawn(move || writer.write_all(&to_send[..]).unwrap());
+
+        let mut buf = vec![0u8; msg.len()];
+        let res = conn.read_exact(&mut buf);
+        expect(&res).to(be_ok());
+        expect(&(buf == msg)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_async_transport_close_flushes_writes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        // Far more than the socket accepts at once, followed right away by a close
+        let msg = (0..8 * 1024 * 1024).map(|i| i as u8).collect::<Vec<u8>>();
+        let to_send = msg.clone();
+        thread::spawn(move || {
+            let pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::new(), SendProtocol::new(to_send));
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut buf = Vec::new();
+        let res = conn.read_to_end(&mut buf);
+        expect(&res).to(be_ok());
+        expect(&buf.len()).to(equal(&msg.len()));
+        expect(&(buf == msg)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_async_transport_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+
+        thread::spawn(move || {
+            let mut pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::new(), EchoProtocol);
+            pipeline.set_idle_timeout(Some(Duration::from_millis(50)));
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // The idle connection is shut down without anything being sent
+        let mut buf = [0u8; 1];
+        let res = conn.read(&mut buf);
+        expect(&res.unwrap()).to(equal(&0));
+    }
+
+    #[test]
+    fn test_async_transport_resume_from_thread() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = MioTcpStream::connect(&addr).unwrap();
+        let protocol = FakeProtocol::new();
+        protocol.lock().unwrap().pause_read = true;
+
+        let server_protocol = protocol.clone();
+        thread::spawn(move || {
+            let pipeline = Pipeline::new(TcpStream::new(stream), FakeCodec::with_frame_len(1), server_protocol);
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.write_all(b"ab").unwrap();
+        expect(&wait_for(&protocol, |p| p.resume_handle.is_some())).to(equal(&true));
+
+        // Reading stays paused until resumed from this thread, like a backend finishing
+        let handle = {
+            let mut p = protocol.lock().unwrap();
+            expect(&p.input).to(equal(&b"a".to_vec()));
+            p.pause_read = false;
+            p.resume_handle.take().unwrap()
+        };
+        handle.resume();
+        expect(&wait_for(&protocol, |p| p.input == b"ab".to_vec())).to(equal(&true));
+    }
+
+    #[test]
+    fn test_async_transport_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let protocol = FakeProtocol::new();
+        run_connect(listener.local_addr().unwrap(), protocol.clone());
+
+        let _conn = listener.accept().unwrap();
+        expect(&wait_for(&protocol, |p| p.spawned)).to(equal(&true));
+        expect(&protocol.lock().unwrap().closed).to(equal(&false));
+    }
+
+    #[test]
+    fn test_async_transport_connect_refused() {
+        let addr = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let protocol = FakeProtocol::new();
+        run_connect(addr, protocol.clone());
+
+        expect(&wait_for(&protocol, |p| p.closed)).to(equal(&true));
+        let p = protocol.lock().unwrap();
+        expect(&p.spawned).to(equal(&false));
+        expect(&p.error_kind).to(equal(&Some(io::ErrorKind::ConnectionRefused)));
+    }
 }
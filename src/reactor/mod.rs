@@ -0,0 +1,39 @@
+//! # Reactor
+//!
+//! Drives pipelines on top of the rotor event loop.
+
+mod wakeup;
+
+mod async_transport;
+pub use self::async_transport::{AsyncTransport, Socket};
+
+mod async_datagram;
+pub use self::async_datagram::AsyncDatagramTransport;
+
+mod server;
+pub use self::server::{Server, Listener};
+
+use rotor::{Loop, Config};
+use netbuf::Buf;
+use transport::tcp::TcpListener;
+use traits::*;
+use std::io;
+use std::net::SocketAddr;
+
+/// Listens on `addr` and runs a pipeline for every accepted TCP connection, using the factories
+/// to create a codec and protocol per connection. Blocks the calling thread running the event
+/// loop.
+pub fn serve<C, P, FC, FP>(addr: &SocketAddr, codec_factory: FC, protocol_factory: FP) -> io::Result<()>
+where C: Codec<Buf>,
+      P: Protocol<Input=C::Output, Output=C::Input>,
+      FC: FnMut() -> C,
+      FP: FnMut() -> P
+{
+    let listener = TcpListener::bind(addr)?;
+    let mut event_loop = Loop::new(&Config::new())?;
+    event_loop.add_machine_with(|scope| {
+        Server::<(), _, _, _, _, _>::new(listener, codec_factory, protocol_factory, scope)
+    }).map_err(|e| io::Error::other(e.to_string()))?;
+
+    event_loop.run(())
+}
@@ -0,0 +1,256 @@
+use rotor::{Response, Scope, GenericScope, Machine, EventSet, Void, SpawnError};
+use rotor::void::unreachable;
+use reactor::{AsyncTransport, Socket};
+use reactor::wakeup::register;
+use pipeline::Pipeline;
+use traits::*;
+use std::io;
+use std::time::Duration;
+
+/// How long to wait before accepting again after the listener failed, e.g. because the process
+/// ran out of file descriptors. Pending connections would otherwise never be accepted, as the
+/// listener is not reported readable again until another connection comes in.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// A listening socket that produces connected transports.
+pub trait Listener: Socket {
+    type Transport: Transport + Socket;
+
+    /// Accepts a pending connection. Returns `None` if no connections are waiting.
+    fn accept(&self) -> io::Result<Option<Self::Transport>>;
+}
+
+struct Acceptor<L, FC, FP> {
+    listener: L,
+    codec_factory: FC,
+    protocol_factory: FP,
+}
+
+enum State<X, L, C, P, FC, FP>
+where L: Listener
+{
+    Accept(Acceptor<L, FC, FP>),
+    Connection(AsyncTransport<X, L::Transport, C, P>),
+}
+
+/// A rotor state machine that accepts connections from a `Listener` and drives a fresh
+/// `Pipeline` for each of them.
+///
+/// Codecs and protocols are created per connection by the supplied factories.
+pub struct Server<X, L, C, P, FC, FP>(State<X, L, C, P, FC, FP>)
+where L: Listener;
+
+impl<X, L, C, P, FC, FP> Server<X, L, C, P, FC, FP>
+where L: Listener,
+      C: Codec<<L::Transport as Transport>::Buffer>,
+      P: Protocol<Input=C::Output, Output=C::Input>,
+      FC: FnMut() -> C,
+      FP: FnMut() -> P
+{
+    /// Registers the listener with the event loop.
+    pub fn new<S>(listener: L,
+                  codec_factory: FC,
+                  protocol_factory: FP,
+                  scope: &mut S) -> Response<Self, Void>
+    where S: GenericScope
+    {
+        if let Err(res) = register(scope, listener.evented(), EventSet::readable()) {
+            return res
+        }
+
+        Response::ok(Server(State::Accept(Acceptor {
+            listener,
+            codec_factory,
+            protocol_factory,
+        })))
+    }
+
+    fn wrap_connection<N>(response: Response<AsyncTransport<X, L::Transport, C, P>, Void>) -> Response<Self, N> {
+        response.map(|c| Server(State::Connection(c)), |void| unreachable(void))
+    }
+}
+
+impl<L, FC, FP> Acceptor<L, FC, FP>
+where L: Listener
+{
+    /// Accepts the next connection and builds its codec and protocol. Returns `None` once no
+    /// connections are pending. Connections aborted before they could be accepted are skipped.
+    fn accept<C, P>(&mut self) -> io::Result<Option<(L::Transport, C, P)>>
+    where FC: FnMut() -> C,
+          FP: FnMut() -> P
+    {
+        loop {
+            match self.listener.accept() {
+                Ok(Some(transport)) => {
+                    let codec = (self.codec_factory)();
+                    let protocol = (self.protocol_factory)();
+                    return Ok(Some((transport, codec, protocol)))
+                },
+                Ok(None) => return Ok(None),
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => {
+                    debug!("server: connection aborted before it was accepted");
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<X, L, C, P, FC, FP> Machine for Server<X, L, C, P, FC, FP>
+where L: Listener,
+      C: Codec<<L::Transport as Transport>::Buffer>,
+      P: Protocol<Input=C::Output, Output=C::Input>,
+      FC: FnMut() -> C,
+      FP: FnMut() -> P
+{
+    type Context = X;
+    type Seed = (L::Transport, C, P);
+
+    fn create((transport, codec, protocol): Self::Seed, scope: &mut Scope<Self::Context>) -> Response<Self, Void> {
+        let pipeline = Pipeline::new(transport, codec, protocol);
+        Self::wrap_connection(AsyncTransport::new(pipeline, scope))
+    }
+
+    fn ready(self, events: EventSet, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        match self.0 {
+            State::Accept(acceptor) => Server(State::Accept(acceptor)).spawned(scope),
+            State::Connection(c) => Self::wrap_connection(c.ready(events, scope)),
+        }
+    }
+
+    /// Keeps accepting until the listener has no more pending connections, one spawn at a
+    /// time. If the listener fails, accepting is retried after `ACCEPT_RETRY`.
+    fn spawned(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        match self.0 {
+            State::Accept(mut acceptor) => {
+                match acceptor.accept() {
+                    Ok(Some(seed)) => Response::spawn(Server(State::Accept(acceptor)), seed),
+                    Ok(None) => Response::ok(Server(State::Accept(acceptor))),
+                    Err(e) => {
+                        error!("server: error accepting connection: {}", e);
+                        let deadline = scope.now() + ACCEPT_RETRY;
+                        Response::ok(Server(State::Accept(acceptor))).deadline(deadline)
+                    },
+                }
+            },
+            State::Connection(c) => Self::wrap_connection(c.spawned(scope)),
+        }
+    }
+
+    fn spawn_error(self, _scope: &mut Scope<Self::Context>, error: SpawnError<Self::Seed>) -> Response<Self, Self::Seed> {
+        error!("server: dropping connection: {}", error);
+        Response::ok(self)
+    }
+
+    fn timeout(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        match self.0 {
+            State::Accept(acceptor) => Server(State::Accept(acceptor)).spawned(scope),
+            State::Connection(c) => Self::wrap_connection(c.timeout(scope)),
+        }
+    }
+
+    fn wakeup(self, scope: &mut Scope<Self::Context>) -> Response<Self, Self::Seed> {
+        match self.0 {
+            State::Accept(acceptor) => Response::ok(Server(State::Accept(acceptor))),
+            State::Connection(c) => Self::wrap_connection(c.wakeup(scope)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use std::net::{SocketAddr, TcpStream as StdTcpStream};
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::time::Duration;
+    use std::cell::Cell;
+    use rotor::mio::tcp::TcpListener as MioTcpListener;
+    use transport::tcp::{TcpListener, TcpStream};
+    use test_helpers::{FakeCodec, EchoProtocol};
+
+    /// Fails the first `failures` accepts, as a listener out of file descriptors would.
+    struct FailingListener {
+        listener: TcpListener,
+        failures: Cell<usize>,
+    }
+
+    impl Socket for FailingListener {
+        type Evented = MioTcpListener;
+
+        fn evented(&self) -> &Self::Evented {
+            self.listener.evented()
+        }
+
+        fn take_socket_error(&self) -> io::Result<()> {
+            self.listener.take_socket_error()
+        }
+    }
+
+    impl Listener for FailingListener {
+        type Transport = TcpStream;
+
+        fn accept(&self) -> io::Result<Option<Self::Transport>> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::other("too many open files"))
+            }
+            self.listener.accept()
+        }
+    }
+
+    fn echo(addr: &SocketAddr, msg: &[u8]) -> Vec<u8> {
+        let mut conn = StdTcpStream::connect(addr).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(msg).unwrap();
+
+        let mut buf = vec![0u8; msg.len()];
+        conn.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_server_pipeline_per_connection() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                Server::<(), _, _, _, _, _>::new(listener, FakeCodec::new, || EchoProtocol, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let first = echo(&addr, b"hello");
+        let second = echo(&addr, b"world");
+        expect(&&first[..]).to(equal(&&b"hello"[..]));
+        expect(&&second[..]).to(equal(&&b"world"[..]));
+    }
+
+    #[test]
+    fn test_server_retries_failed_accept() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = FailingListener {
+            listener,
+            failures: Cell::new(1),
+        };
+
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                Server::<(), _, _, _, _, _>::new(listener, FakeCodec::new, || EchoProtocol, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        // No other connection comes in to report the listener readable again
+        let echoed = echo(&addr, b"hello");
+        expect(&&echoed[..]).to(equal(&&b"hello"[..]));
+    }
+}
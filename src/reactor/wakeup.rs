@@ -0,0 +1,40 @@
+use rotor::{GenericScope, Response, Evented, EventSet, PollOpt, Void};
+
+/// Registers a machine's socket with the event loop for edge triggered `interest`, or returns
+/// the response that fails the machine's creation.
+pub fn register<S, E, M>(scope: &mut S, evented: &E, interest: EventSet) -> Result<(), Response<M, Void>>
+where S: GenericScope,
+      E: Evented
+{
+    scope.register(evented, interest, PollOpt::edge()).map_err(|e| {
+        error!("reactor: could not register socket: {}", e);
+        Response::error(Box::new(e))
+    })
+}
+
+/// Schedules another pass if the pipeline has one outstanding. Going through a wakeup lets other
+/// machines on the loop run in between passes.
+pub fn schedule_pass<S>(scope: &mut S, pending: bool)
+where S: GenericScope
+{
+    if !pending {
+        return
+    }
+
+    if let Err(e) = scope.notifier().wakeup() {
+        error!("reactor: could not schedule pass: {:?}", e);
+    }
+}
+
+/// Returns a function that wakes up the machine behind `scope` from any thread, for use as the
+/// waker of a pipeline's `ResumeHandle`.
+pub fn waker<S>(scope: &mut S) -> impl Fn() + Send + 'static
+where S: GenericScope
+{
+    let notifier = scope.notifier();
+    move || {
+        if let Err(e) = notifier.wakeup() {
+            error!("reactor: could not wake up machine: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use traits::*;
+use future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Writes every message it receives straight back to the peer.
+pub struct EchoProtocol;
+
+impl Protocol for EchoProtocol {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn spawned<C>(&mut self, _ctx: &mut C) where C: Context {}
+
+    fn closed<C>(&mut self, _ctx: &mut C, _err: Option<&io::Error>) where C: Context {}
+
+    fn received_data<C>(&mut self, ctx: &mut C, data: Self::Input) where C: Context<Write=Self::Output> {
+        ctx.write(data).unwrap();
+    }
+
+    fn writable<C>(&mut self, _ctx: &mut C) where C: Context<Write=Self::Output> {}
+}
+
+/// Sends every datagram it receives back to where it came from, keeping the future of the last
+/// write.
+#[derive(Clone)]
+pub struct DatagramEchoProtocol {
+    future: Arc<Mutex<Option<Future<()>>>>,
+}
+
+impl DatagramEchoProtocol {
+    pub fn new() -> DatagramEchoProtocol {
+        DatagramEchoProtocol {
+            future: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn take_future(&self) -> Option<Future<()>> {
+        self.future.lock().unwrap().take()
+    }
+}
+
+impl DatagramProtocol for DatagramEchoProtocol {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn spawned<C>(&mut self, _ctx: &mut C) where C: DatagramContext {}
+
+    fn closed<C>(&mut self, _ctx: &mut C, _err: Option<&io::Error>) where C: DatagramContext {}
+
+    fn received_data<C>(&mut self, ctx: &mut C, data: Self::Input, addr: SocketAddr) where C: DatagramContext<Write=Self::Output> {
+        let f = ctx.write_to(data, addr).unwrap();
+        *self.future.lock().unwrap() = Some(f);
+    }
+
+    fn writable<C>(&mut self, _ctx: &mut C) where C: DatagramContext<Write=Self::Output> {}
+}
@@ -4,6 +4,8 @@ use std::io::{self, Write};
 pub struct FakeCodec {
     pub decoded: Vec<u8>,
     pub encoded: Vec<u8>,
+    frame_len: Option<usize>,
+    decode_error: bool,
 }
 
 impl FakeCodec {
@@ -11,24 +13,50 @@ impl FakeCodec {
         FakeCodec {
             decoded: Vec::new(),
             encoded: Vec::new(),
+            frame_len: None,
+            decode_error: false,
+        }
+    }
+
+    /// Fails every decode with an `InvalidData` error.
+    pub fn failing() -> FakeCodec {
+        FakeCodec {
+            decode_error: true,
+            ..FakeCodec::new()
+        }
+    }
+
+    /// Decodes frames of exactly `len` bytes instead of the whole buffer.
+    pub fn with_frame_len(len: usize) -> FakeCodec {
+        FakeCodec {
+            frame_len: Some(len),
+            ..FakeCodec::new()
         }
     }
 }
 
-impl Codec<Vec<u8>> for FakeCodec {
+impl<B: Write> Codec<B> for FakeCodec {
     type Input = Vec<u8>;
     type Output = Vec<u8>;
 
     /// Codec should write encoded data to buffer and finish the promise.
-    fn encode(&mut self, buffer: &mut Vec<u8>, input: Self::Input) -> io::Result<()> {
+    fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
         self.encoded.write_all(&input[..]).unwrap();
         buffer.write_all(&input[..])
     }
 
-    // If decode returns None that means the Codec needs more data, otherwise it returns a tuple of
-    // the number of bytes used and an Output object.
-    fn decode(&mut self, buffer: &[u8]) -> Option<(usize, Self::Output)> {
-        self.decoded.write_all(buffer).unwrap();
-        Some((buffer.len(), buffer.to_vec()))
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        if self.decode_error {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "test decode error"))
+        }
+
+        let len = self.frame_len.unwrap_or(buffer.len());
+        if buffer.is_empty() || buffer.len() < len {
+            return Ok(None)
+        }
+
+        let frame = &buffer[..len];
+        self.decoded.write_all(frame).unwrap();
+        Ok(Some((len, frame.to_vec())))
     }
 }
@@ -0,0 +1,89 @@
+use traits::*;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::io::{self};
+
+/// Receives the datagrams queued in `inbound` and records every datagram sent.
+pub struct FakeDatagramTransport {
+    assertions: Arc<Mutex<DatagramAssertions>>,
+    received: Vec<u8>,
+}
+
+pub struct DatagramAssertions {
+    pub spawned: bool,
+    pub closed: bool,
+    pub error_kind: Option<io::ErrorKind>,
+    pub inbound: VecDeque<(Vec<u8>, SocketAddr)>,
+    pub sent: Vec<(Vec<u8>, SocketAddr)>,
+    pub send_blocked: bool,
+    pub send_error: Option<io::ErrorKind>,
+    pub recv_error: Option<io::ErrorKind>,
+}
+
+impl DatagramAssertions {
+    pub fn new() -> Arc<Mutex<DatagramAssertions>> {
+        Arc::new(Mutex::new(DatagramAssertions {
+            spawned: false,
+            closed: false,
+            error_kind: None,
+            inbound: VecDeque::new(),
+            sent: Vec::new(),
+            send_blocked: false,
+            send_error: None,
+            recv_error: None,
+        }))
+    }
+}
+
+impl FakeDatagramTransport {
+    pub fn new(assertions: Arc<Mutex<DatagramAssertions>>) -> FakeDatagramTransport {
+        FakeDatagramTransport {
+            assertions,
+            received: Vec::new(),
+        }
+    }
+}
+
+impl DatagramTransport for FakeDatagramTransport {
+    fn spawned(&mut self) {
+        let mut a = self.assertions.lock().unwrap();
+        a.spawned = true
+    }
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        let mut a = self.assertions.lock().unwrap();
+        a.closed = true;
+        if let Some(e) = err {
+            a.error_kind = Some(e.kind());
+        }
+    }
+
+    fn recv_from(&mut self) -> io::Result<Option<(&[u8], SocketAddr)>> {
+        let mut a = self.assertions.lock().unwrap();
+        if let Some(e) = a.recv_error {
+            return Err(io::Error::new(e, "test error"))
+        }
+
+        match a.inbound.pop_front() {
+            Some((datagram, addr)) => {
+                self.received = datagram;
+                Ok(Some((&self.received[..], addr)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn send_to(&mut self, datagram: &[u8], addr: &SocketAddr) -> io::Result<bool> {
+        let mut a = self.assertions.lock().unwrap();
+        if let Some(e) = a.send_error {
+            return Err(io::Error::new(e, "test error"))
+        }
+        if a.send_blocked {
+            return Ok(false)
+        }
+
+        a.sent.push((datagram.to_vec(), *addr));
+        Ok(true)
+    }
+}
@@ -1,7 +1,10 @@
 use std::sync::{Arc, Mutex};
 use future::{Future};
+use pipeline::ResumeHandle;
+use transport::tls::TlsInfo;
 use traits::*;
 use std::io::{self, Write};
+use std::time::Duration;
 
 pub struct FakeProtocol {
     pub input: Vec<u8>,
@@ -9,6 +12,26 @@ pub struct FakeProtocol {
     pub future: Option<Future<()>>,
     pub spawned: bool,
     pub closed: bool,
+    pub closed_count: usize,
+    pub error_kind: Option<io::ErrorKind>,
+    /// Close the context after every callback
+    pub close: bool,
+    /// Cancel every write right after scheduling it
+    pub cancel: bool,
+    /// Number of writes rejected by the context
+    pub rejected: usize,
+    /// Number of times `output` is written per callback
+    pub batch: usize,
+    /// Pause reading after every message, resume from `writable` once cleared
+    pub pause_read: bool,
+    /// Handle to resume reading with, kept when pausing
+    pub resume_handle: Option<ResumeHandle>,
+    /// TLS details seen by the last message received
+    pub tls_info: Option<TlsInfo>,
+    /// Timeout to set once spawned
+    pub timeout: Option<Duration>,
+    /// Number of times the timeout expired
+    pub timed_out: usize,
 }
 
 impl FakeProtocol {
@@ -19,33 +42,85 @@ impl FakeProtocol {
             future: None,
             spawned: false,
             closed: false,
+            closed_count: 0,
+            error_kind: None,
+            close: false,
+            cancel: false,
+            rejected: 0,
+            batch: 1,
+            pause_read: false,
+            resume_handle: None,
+            tls_info: None,
+            timeout: None,
+            timed_out: 0,
         }))
     }
 }
 
+impl FakeProtocol {
+    fn write<C>(&mut self, ctx: &mut C) where C: Context<Write=Vec<u8>> {
+        for _ in 0..self.batch {
+            match ctx.write(self.output.clone()) {
+                Ok(f) => {
+                    if self.cancel {
+                        f.cancel();
+                    }
+                    self.future = Some(f);
+                },
+                Err(_) => self.rejected += 1,
+            }
+        }
+        if self.close {
+            ctx.close();
+        }
+    }
+}
+
 impl Protocol for Arc<Mutex<FakeProtocol>> {
     type Input = Vec<u8>;
     type Output = Vec<u8>;
 
     fn spawned<C>(&mut self, ctx: &mut C) where C: Context {
-        self.lock().unwrap().spawned = true;
+        let mut p = self.lock().unwrap();
+        p.spawned = true;
+        if let Some(timeout) = p.timeout {
+            ctx.set_timeout(timeout);
+        }
+        if p.close {
+            ctx.close();
+        }
     }
 
-    fn closed<C>(&mut self, ctx: &mut C, err: Option<&io::Error>) where C: Context {
-        self.lock().unwrap().closed = true;
+    fn closed<C>(&mut self, _ctx: &mut C, err: Option<&io::Error>) where C: Context {
+        let mut p = self.lock().unwrap();
+        p.closed = true;
+        p.closed_count += 1;
+        p.error_kind = err.map(|e| e.kind());
     }
 
     fn received_data<C>(&mut self, ctx: &mut C, data: Self::Input) where C: Context<Write=Self::Output> {
         let mut p = self.lock().unwrap();
         p.input.write_all(&data[..]).unwrap();
-        let f = ctx.write(p.output.clone()).unwrap();
-        p.future = Some(f);
+        p.tls_info = ctx.tls_info().cloned();
+        p.write(ctx);
+        if p.pause_read {
+            ctx.pause_read();
+            p.resume_handle = Some(ctx.resume_handle());
+        }
     }
 
     /// Called when socket changes state to being writable.
     fn writable<C>(&mut self, ctx: &mut C) where C: Context<Write=Self::Output> {
         let mut p = self.lock().unwrap();
-        let f = ctx.write(p.output.clone()).unwrap();
-        p.future = Some(f);
+        if !p.pause_read {
+            ctx.resume_read();
+        }
+        p.write(ctx);
+    }
+
+    fn timeout<C>(&mut self, ctx: &mut C) where C: Context<Write=Self::Output> {
+        let mut p = self.lock().unwrap();
+        p.timed_out += 1;
+        p.write(ctx);
     }
 }
@@ -2,8 +2,12 @@ use traits::*;
 use std::sync::{Arc, Mutex};
 use std::io::{self};
 
+/// Reads the initial contents of `buf`. Anything written afterwards is appended to `buf` but never
+/// read back. Written bytes count as flushed on `writable`, unless `write_blocked` is set.
 pub struct FakeTransport<'a> {
     buf: &'a mut Vec<u8>,
+    readable: usize,
+    flushed: usize,
     assertions: Arc<Mutex<TransportAssertions>>,
     read_error: Option<io::ErrorKind>,
 }
@@ -13,6 +17,8 @@ pub struct TransportAssertions {
     pub closed: bool,
     pub error_kind: Option<io::ErrorKind>,
     pub writable: bool,
+    pub write_blocked: bool,
+    pub write_error: Option<io::ErrorKind>,
 }
 
 impl TransportAssertions {
@@ -22,6 +28,8 @@ impl TransportAssertions {
             closed: false,
             error_kind: None,
             writable: false,
+            write_blocked: false,
+            write_error: None,
         }))
     }
 }
@@ -29,9 +37,11 @@ impl TransportAssertions {
 impl<'a> FakeTransport<'a> {
     pub fn new(buf: &'a mut Vec<u8>, assertions: Arc<Mutex<TransportAssertions>>, read_error: Option<io::ErrorKind>) -> FakeTransport<'a> {
         FakeTransport {
-            buf: buf,
-            read_error: read_error,
-            assertions: assertions,
+            readable: buf.len(),
+            flushed: 0,
+            buf,
+            read_error,
+            assertions,
         }
     }
 }
@@ -40,7 +50,7 @@ impl<'t> Transport for FakeTransport<'t> {
     type Buffer = Vec<u8>;
 
     fn buffer(&mut self) -> &mut Self::Buffer {
-        &mut self.buf
+        self.buf
     }
 
     fn spawned(&mut self) {
@@ -51,26 +61,37 @@ impl<'t> Transport for FakeTransport<'t> {
     fn closed(&mut self, err: Option<&io::Error>) {
         let mut a = self.assertions.lock().unwrap();
         a.closed = true;
-        err.map(|e| {
+        if let Some(e) = err {
             a.error_kind = Some(e.kind());
-        });
+        }
     }
 
     fn read(&mut self) -> io::Result<&[u8]> {
         match self.read_error {
-            None => { Ok(&self.buf[..]) },
+            None => { Ok(&self.buf[..self.readable]) },
             Some(e) => { Err(io::Error::new(e, "test error")) },
         }
     }
 
     fn consume(&mut self, num: usize) {
-        self.buf.clear();
+        self.buf.drain(..num);
+        self.readable -= num;
+    }
+
+    fn buffered(&self) -> usize {
+        self.buf.len() - self.readable - self.flushed
     }
 
-    /// Called when socket changes state to being writable. This method should return any data that
-    /// the stage wants to write to the socket.
-    fn writable(&mut self) {
+    fn writable(&mut self) -> io::Result<()> {
         let mut a = self.assertions.lock().unwrap();
-        a.writable = true
+        a.writable = true;
+        if let Some(e) = a.write_error {
+            return Err(io::Error::new(e, "test error"))
+        }
+
+        if !a.write_blocked {
+            self.flushed = self.buf.len() - self.readable;
+        }
+        Ok(())
     }
 }
@@ -1,8 +1,23 @@
 mod fake_transport;
 pub use test_helpers::fake_transport::{FakeTransport, TransportAssertions};
 
+mod fake_datagram_transport;
+pub use test_helpers::fake_datagram_transport::{FakeDatagramTransport, DatagramAssertions};
+
+mod fake_tls_session;
+pub use test_helpers::fake_tls_session::{FakeTlsSession};
+
 mod fake_codec;
 pub use test_helpers::fake_codec::{FakeCodec};
 
 mod fake_protocol;
 pub use test_helpers::fake_protocol::{FakeProtocol};
+
+mod echo_protocol;
+pub use test_helpers::echo_protocol::{EchoProtocol, DatagramEchoProtocol};
+
+mod send_protocol;
+pub use test_helpers::send_protocol::{SendProtocol};
+
+mod stream_protocol;
+pub use test_helpers::stream_protocol::{StreamProtocol};
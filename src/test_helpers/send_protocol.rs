@@ -0,0 +1,33 @@
+use traits::*;
+use std::io;
+
+/// Writes `data` the first time the pipeline is writable, and closes the pipeline right after.
+pub struct SendProtocol {
+    data: Option<Vec<u8>>,
+}
+
+impl SendProtocol {
+    pub fn new(data: Vec<u8>) -> SendProtocol {
+        SendProtocol {
+            data: Some(data),
+        }
+    }
+}
+
+impl Protocol for SendProtocol {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn spawned<C>(&mut self, _ctx: &mut C) where C: Context {}
+
+    fn closed<C>(&mut self, _ctx: &mut C, _err: Option<&io::Error>) where C: Context {}
+
+    fn received_data<C>(&mut self, _ctx: &mut C, _data: Self::Input) where C: Context<Write=Self::Output> {}
+
+    fn writable<C>(&mut self, ctx: &mut C) where C: Context<Write=Self::Output> {
+        if let Some(data) = self.data.take() {
+            ctx.write(data).unwrap();
+            ctx.close();
+        }
+    }
+}
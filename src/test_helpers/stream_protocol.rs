@@ -0,0 +1,43 @@
+use traits::*;
+use std::io;
+
+/// Once the first message arrives, writes `count` one byte messages, as many per callback as the
+/// context accepts.
+pub struct StreamProtocol {
+    left: usize,
+    started: bool,
+}
+
+impl StreamProtocol {
+    pub fn new(count: usize) -> StreamProtocol {
+        StreamProtocol {
+            left: count,
+            started: false,
+        }
+    }
+
+    fn write<C>(&mut self, ctx: &mut C) where C: Context<Write=Vec<u8>> {
+        while self.started && self.left > 0 && ctx.is_writable() {
+            ctx.write(vec!(self.left as u8)).unwrap();
+            self.left -= 1;
+        }
+    }
+}
+
+impl Protocol for StreamProtocol {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn spawned<C>(&mut self, _ctx: &mut C) where C: Context {}
+
+    fn closed<C>(&mut self, _ctx: &mut C, _err: Option<&io::Error>) where C: Context {}
+
+    fn received_data<C>(&mut self, ctx: &mut C, _data: Self::Input) where C: Context<Write=Self::Output> {
+        self.started = true;
+        self.write(ctx);
+    }
+
+    fn writable<C>(&mut self, ctx: &mut C) where C: Context<Write=Self::Output> {
+        self.write(ctx);
+    }
+}
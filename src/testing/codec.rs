@@ -0,0 +1,48 @@
+use traits::*;
+use std::io::{self, Write};
+
+/// Passes bytes through unchanged, decoding everything available as one message.
+#[derive(Default)]
+pub struct BytesCodec;
+
+impl BytesCodec {
+    pub fn new() -> BytesCodec {
+        BytesCodec
+    }
+}
+
+impl<B: Write> Codec<B> for BytesCodec {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> {
+        buffer.write_all(&input[..])
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>> {
+        if buffer.is_empty() {
+            return Ok(None)
+        }
+        Ok(Some((buffer.len(), buffer.to_vec())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+
+    #[test]
+    fn test_bytes_codec() {
+        let mut codec = BytesCodec::new();
+        let mut buffer = Vec::new();
+
+        expect(&codec.encode(&mut buffer, vec!(1, 2))).to(be_ok());
+        expect(&buffer).to(equal(&vec!(1, 2)));
+
+        let decode = <BytesCodec as Codec<Vec<u8>>>::decode(&mut codec, &buffer[..]);
+        expect(&decode.unwrap()).to(equal(&Some((2, vec!(1, 2)))));
+        let decode = <BytesCodec as Codec<Vec<u8>>>::decode(&mut codec, &[]);
+        expect(&decode.unwrap()).to(be_none());
+    }
+}
@@ -0,0 +1,179 @@
+use future::{Future, Promise, pair};
+use pipeline::ResumeHandle;
+use transport::tls::TlsInfo;
+use traits::*;
+use std::io;
+use std::time::Duration;
+
+/// A `Context` that records what a protocol does with it, for calling protocol callbacks
+/// directly in tests.
+///
+/// Write futures stay pending until `complete_writes` or `fail_writes` is called.
+pub struct RecordingContext<W> {
+    writes: Vec<W>,
+    promises: Vec<Promise<()>>,
+    writable: bool,
+    closed: bool,
+    read_paused: bool,
+    resume: ResumeHandle,
+    tls_info: Option<TlsInfo>,
+    timeouts: Vec<Duration>,
+}
+
+impl<W> RecordingContext<W> {
+    pub fn new() -> RecordingContext<W> {
+        RecordingContext {
+            writes: Vec::new(),
+            promises: Vec::new(),
+            writable: true,
+            closed: false,
+            read_paused: false,
+            resume: ResumeHandle::new(),
+            tls_info: None,
+            timeouts: Vec::new(),
+        }
+    }
+
+    /// Returns every object written so far, in order.
+    pub fn writes(&self) -> &[W] {
+        &self.writes[..]
+    }
+
+    /// Returns and forgets the objects written so far.
+    pub fn take_writes(&mut self) -> Vec<W> {
+        self.writes.drain(..).collect()
+    }
+
+    /// Resolves the futures of every write made so far.
+    pub fn complete_writes(&mut self) {
+        for promise in self.promises.drain(..) {
+            let _ = promise.set(Ok(()));
+        }
+    }
+
+    /// Fails the futures of every write made so far with an error of the given kind.
+    pub fn fail_writes(&mut self, kind: io::ErrorKind) {
+        for promise in self.promises.drain(..) {
+            let _ = promise.set(Err(io::Error::new(kind, "write failed")));
+        }
+    }
+
+    /// Makes further writes get rejected, as they would be above the high watermark.
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    /// Returns true once the protocol has asked to close.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn is_read_paused(&self) -> bool {
+        self.read_paused
+    }
+
+    /// Sets what `tls_info` returns, as after a TLS handshake.
+    pub fn set_tls_info(&mut self, info: Option<TlsInfo>) {
+        self.tls_info = info;
+    }
+
+    /// Returns every timeout set so far, in order.
+    pub fn timeouts(&self) -> &[Duration] {
+        &self.timeouts[..]
+    }
+}
+
+impl<W> Default for RecordingContext<W> {
+    fn default() -> RecordingContext<W> {
+        RecordingContext::new()
+    }
+}
+
+impl<W> Context for RecordingContext<W> {
+    type Write = W;
+
+    fn write(&mut self, obj: Self::Write) -> Result<Future<()>, Self::Write> {
+        if !self.writable {
+            return Err(obj)
+        }
+
+        let (promise, future) = pair();
+        self.writes.push(obj);
+        self.promises.push(promise);
+        Ok(future)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn pause_read(&mut self) {
+        self.read_paused = true;
+    }
+
+    fn resume_read(&mut self) {
+        self.read_paused = false;
+    }
+
+    /// The handle is not connected to a pipeline, check `is_resume_requested` on it instead.
+    fn resume_handle(&self) -> ResumeHandle {
+        self.resume.clone()
+    }
+
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeouts.push(timeout);
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+
+    #[test]
+    fn test_recording_context_writes() {
+        let mut ctx = RecordingContext::new();
+        let first = ctx.write(1u8).unwrap();
+        let second = ctx.write(2u8).unwrap();
+        expect(&ctx.writes()).to(equal(&&[1u8, 2][..]));
+        expect(&first.is_done()).to(equal(&false));
+
+        ctx.complete_writes();
+        expect(&first.get()).to(be_ok());
+        expect(&second.get()).to(be_ok());
+
+        expect(&ctx.take_writes()).to(equal(&vec!(1, 2)));
+        expect(&ctx.writes().len()).to(equal(&0));
+    }
+
+    #[test]
+    fn test_recording_context_fail_writes() {
+        let mut ctx = RecordingContext::new();
+        let future = ctx.write(1u8).unwrap();
+
+        ctx.fail_writes(io::ErrorKind::BrokenPipe);
+        expect(&future.get().unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_recording_context_state() {
+        let mut ctx = RecordingContext::<u8>::new();
+        ctx.set_writable(false);
+        expect(&ctx.write(1u8).unwrap_err()).to(equal(&1));
+
+        ctx.pause_read();
+        ctx.set_timeout(Duration::from_secs(1));
+        ctx.close();
+        expect(&ctx.is_read_paused()).to(equal(&true));
+        expect(&ctx.timeouts()).to(equal(&&[Duration::from_secs(1)][..]));
+        expect(&ctx.is_closed()).to(equal(&true));
+    }
+}
@@ -0,0 +1,162 @@
+use pipeline::Pipeline;
+use testing::ScriptedTransport;
+use traits::*;
+use std::io;
+
+/// Runs a codec and protocol through a `Pipeline` on top of a `ScriptedTransport`.
+///
+/// ```ignore
+/// let mut harness = Harness::new(LinesCodec::<Vec<u8>>::new(), EchoProtocol);
+/// harness.feed(b"hel");
+/// harness.feed(b"lo\n");
+/// harness.assert_written(b"hello\n");
+/// ```
+pub struct Harness<C, P> {
+    pipeline: Pipeline<ScriptedTransport, C, P>,
+}
+
+impl<C, P> Harness<C, P>
+where C: Codec<Vec<u8>>,
+      P: Protocol<Input=C::Output, Output=C::Input>
+{
+    /// Creates the pipeline and spawns it.
+    pub fn new(codec: C, protocol: P) -> Harness<C, P> {
+        let mut pipeline = Pipeline::new(ScriptedTransport::new(), codec, protocol);
+        pipeline.spawned();
+        Harness {
+            pipeline,
+        }
+    }
+
+    /// Delivers `data` as one chunk and lets the pipeline decode everything it can.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.pipeline.transport_mut().push_read(data);
+        self.readable();
+    }
+
+    /// Fails the next read with an error of the given kind.
+    pub fn feed_error(&mut self, kind: io::ErrorKind) {
+        self.pipeline.transport_mut().push_read_error(kind);
+        self.readable();
+    }
+
+    fn readable(&mut self) {
+        self.pipeline.readable();
+        self.run_pending();
+    }
+
+    /// Runs the read and write passes the pipeline left outstanding because of its budgets.
+    fn run_pending(&mut self) {
+        loop {
+            if self.pipeline.is_read_pending() {
+                self.pipeline.readable();
+            } else if self.pipeline.is_write_pending() {
+                self.pipeline.writable();
+            } else {
+                return
+            }
+        }
+    }
+
+    /// Signals that the transport is writable.
+    pub fn writable(&mut self) {
+        self.pipeline.writable();
+        self.run_pending();
+    }
+
+    /// Expires the earliest pending timeout, if any.
+    pub fn expire_timeout(&mut self) {
+        if let Some(deadline) = self.pipeline.next_deadline() {
+            self.pipeline.timeout(deadline);
+        }
+    }
+
+    /// Returns and forgets everything written so far.
+    pub fn take_written(&mut self) -> Vec<u8> {
+        self.pipeline.transport_mut().take_written()
+    }
+
+    /// Panics unless exactly `expected` has been written since the last check.
+    pub fn assert_written(&mut self, expected: &[u8]) {
+        let written = self.take_written();
+        assert!(written == expected,
+                "expected {:?} to be written, got {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&written[..]));
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.pipeline.is_closed()
+    }
+
+    /// Returns the kind of the error the pipeline was closed with, if any.
+    pub fn close_error(&self) -> Option<io::ErrorKind> {
+        self.pipeline.transport().close_error()
+    }
+
+    pub fn protocol(&self) -> &P {
+        self.pipeline.protocol()
+    }
+
+    pub fn protocol_mut(&mut self) -> &mut P {
+        self.pipeline.protocol_mut()
+    }
+
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline<ScriptedTransport, C, P> {
+        &mut self.pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use codec::lines::LinesCodec;
+    use testing::BytesCodec;
+    use test_helpers::{EchoProtocol, FakeProtocol};
+    use std::time::Duration;
+
+    #[test]
+    fn test_harness_chunks() {
+        let mut harness = Harness::new(LinesCodec::<Vec<u8>>::new(), EchoProtocol);
+
+        harness.feed(b"hel");
+        harness.assert_written(b"");
+
+        harness.feed(b"lo\nworld\n");
+        harness.assert_written(b"hello\nworld\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected \"pong\" to be written")]
+    fn test_harness_assert_written() {
+        let mut harness = Harness::new(BytesCodec::new(), EchoProtocol);
+        harness.feed(b"ping");
+        harness.assert_written(b"pong");
+    }
+
+    #[test]
+    fn test_harness_read_error() {
+        let mut harness = Harness::new(BytesCodec::new(), EchoProtocol);
+        harness.feed_error(io::ErrorKind::ConnectionReset);
+
+        expect(&harness.is_closed()).to(equal(&true));
+        expect(&harness.close_error()).to(equal(&Some(io::ErrorKind::ConnectionReset)));
+    }
+
+    #[test]
+    fn test_harness_timeout() {
+        let protocol = FakeProtocol::new();
+        {
+            let mut p = protocol.lock().unwrap();
+            p.output = b"tick".to_vec();
+            p.timeout = Some(Duration::from_secs(30));
+        }
+        let mut harness = Harness::new(BytesCodec::new(), protocol.clone());
+        harness.take_written();
+
+        harness.expire_timeout();
+        harness.assert_written(b"tick");
+        expect(&(protocol.lock().unwrap().timed_out)).to(equal(&1));
+    }
+}
@@ -0,0 +1,20 @@
+//! # Testing
+//!
+//! Tools for unit testing codecs and protocols without a network, enabled by the `testing`
+//! feature.
+//!
+//! `RecordingContext` captures everything a protocol does from a single callback, while
+//! `Harness` runs a codec and protocol through a real `Pipeline` on top of a
+//! `ScriptedTransport`.
+
+mod context;
+pub use self::context::RecordingContext;
+
+mod transport;
+pub use self::transport::ScriptedTransport;
+
+mod codec;
+pub use self::codec::BytesCodec;
+
+mod harness;
+pub use self::harness::Harness;
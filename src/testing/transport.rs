@@ -0,0 +1,155 @@
+use traits::*;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+
+enum Step {
+    Data(Vec<u8>),
+    Error(io::ErrorKind),
+}
+
+/// A transport that plays back a script of incoming chunks and read errors, one step per
+/// `read` call, and records everything written to it.
+pub struct ScriptedTransport {
+    script: VecDeque<Step>,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    written: Vec<u8>,
+    max_write: Option<usize>,
+    closed: bool,
+    close_error: Option<io::ErrorKind>,
+}
+
+impl ScriptedTransport {
+    pub fn new() -> ScriptedTransport {
+        ScriptedTransport {
+            script: VecDeque::new(),
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            written: Vec::new(),
+            max_write: None,
+            closed: false,
+            close_error: None,
+        }
+    }
+
+    /// Queues a chunk of incoming data.
+    pub fn push_read(&mut self, data: &[u8]) {
+        self.script.push_back(Step::Data(data.to_vec()));
+    }
+
+    /// Queues a read error of the given kind.
+    pub fn push_read_error(&mut self, kind: io::ErrorKind) {
+        self.script.push_back(Step::Error(kind));
+    }
+
+    /// Returns true once every scripted step has been read.
+    pub fn is_script_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    /// Limits how many bytes each `writable` call flushes, to simulate partial writes.
+    /// Unlimited by default.
+    pub fn set_max_write(&mut self, max: Option<usize>) {
+        self.max_write = max;
+    }
+
+    /// Returns everything flushed so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written[..]
+    }
+
+    /// Returns and forgets everything flushed so far.
+    pub fn take_written(&mut self) -> Vec<u8> {
+        self.written.drain(..).collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the kind of the error the transport was closed with, if any.
+    pub fn close_error(&self) -> Option<io::ErrorKind> {
+        self.close_error
+    }
+}
+
+impl Default for ScriptedTransport {
+    fn default() -> ScriptedTransport {
+        ScriptedTransport::new()
+    }
+}
+
+impl Transport for ScriptedTransport {
+    type Buffer = Vec<u8>;
+
+    fn buffer(&mut self) -> &mut Self::Buffer {
+        &mut self.write_buffer
+    }
+
+    fn spawned(&mut self) {}
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        self.closed = true;
+        self.close_error = err.map(|e| e.kind());
+    }
+
+    fn read(&mut self) -> io::Result<&[u8]> {
+        match self.script.pop_front() {
+            Some(Step::Data(data)) => self.read_buffer.extend(data),
+            Some(Step::Error(kind)) => return Err(io::Error::new(kind, "scripted read error")),
+            None => {},
+        }
+        Ok(&self.read_buffer[..])
+    }
+
+    /// Tells transport that "bytes" number of bytes have been read
+    fn consume(&mut self, bytes: usize) {
+        self.read_buffer.drain(..bytes);
+    }
+
+    fn buffered(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    fn writable(&mut self) -> io::Result<()> {
+        let len = cmp::min(self.write_buffer.len(), self.max_write.unwrap_or(usize::MAX));
+        self.written.extend(self.write_buffer.drain(..len));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_scripted_transport_reads() {
+        let mut transport = ScriptedTransport::new();
+        transport.push_read(b"ab");
+        transport.push_read_error(io::ErrorKind::ConnectionReset);
+        transport.push_read(b"c");
+
+        expect(&transport.read().unwrap().to_vec()).to(equal(&b"ab".to_vec()));
+        expect(&transport.read().unwrap_err().kind()).to(equal(&io::ErrorKind::ConnectionReset));
+        transport.consume(1);
+        expect(&transport.read().unwrap().to_vec()).to(equal(&b"bc".to_vec()));
+        expect(&transport.is_script_done()).to(equal(&true));
+    }
+
+    #[test]
+    fn test_scripted_transport_partial_writes() {
+        let mut transport = ScriptedTransport::new();
+        transport.set_max_write(Some(2));
+        transport.buffer().write_all(b"abc").unwrap();
+
+        expect(&transport.writable()).to(be_ok());
+        expect(&transport.written()).to(equal(&&b"ab"[..]));
+        expect(&transport.buffered()).to(equal(&1));
+
+        expect(&transport.writable()).to(be_ok());
+        expect(&transport.take_written()).to(equal(&b"abc".to_vec()));
+    }
+}
@@ -1,5 +1,9 @@
-use future::{Future, Promise};
+use future::{Future};
+use pipeline::ResumeHandle;
+use transport::tls::TlsInfo;
 use std::io::{self};
+use std::net::SocketAddr;
+use std::time::Duration;
 
 /// Owns the socket
 pub trait Transport {
@@ -14,8 +18,22 @@ pub trait Transport {
     fn read(&mut self) -> io::Result<&[u8]>;
     /// Tells transport that "bytes" number of bytes have been read
     fn consume(&mut self, bytes: usize);
-    /// Called when socket changes state to being writable.
-    fn writable(&mut self);
+    /// Returns the number of bytes written to the buffer that have not been flushed to the socket
+    /// yet.
+    fn buffered(&self) -> usize;
+    /// Called when socket changes state to being writable, and after new data has been encoded.
+    /// Flushes as much of the buffer as the socket accepts.
+    fn writable(&mut self) -> io::Result<()>;
+    /// Returns true once the peer has closed its side and everything it sent has been read.
+    /// Sockets leave this to the reactor, which reports the hangup to the pipeline.
+    fn is_peer_closed(&self) -> bool {
+        false
+    }
+    /// Returns what the TLS handshake established, once it is done. Transports that do not
+    /// encrypt return `None`.
+    fn tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
 }
 
 pub trait Codec<B> {
@@ -24,29 +42,95 @@ pub trait Codec<B> {
 
     /// Codec should write encoded data to buffer and finish the promise.
     fn encode(&mut self, buffer: &mut B, input: Self::Input) -> io::Result<()> ;
-    // If decode returns None that means the Codec needs more data, otherwise it returns a tuple of
-    // the number of bytes used and an Output object.
-    fn decode(&mut self, buffer: &[u8]) -> Option<(usize, Self::Output)>;
+    /// If decode returns `Ok(None)` that means the Codec needs more data, otherwise it returns a
+    /// tuple of the number of bytes used and an Output object. An error means the stream cannot
+    /// be decoded any further, and the pipeline is closed with it.
+    fn decode(&mut self, buffer: &[u8]) -> io::Result<Option<(usize, Self::Output)>>;
 }
 
 pub trait Protocol {
     type Output;
     type Input;
 
-    /// Does not currently respect ctx.close()
+    /// Called once the transport is connected. The protocol may close the pipeline from here.
     fn spawned<C>(&mut self, ctx: &mut C) where C: Context;
     /// Optional io error provided
     fn closed<C>(&mut self, ctx: &mut C, err: Option<&io::Error>) where C: Context;
     fn received_data<C>(&mut self, ctx: &mut C, data: Self::Input) where C: Context<Write=Self::Output>;
-    /// Called when socket changes state to being writable.
+    /// Called when the pipeline can accept writes again: once spawned, when the socket becomes
+    /// writable, and when the write buffer drains below its low watermark.
     fn writable<C>(&mut self, ctx: &mut C) where C: Context<Write=Self::Output>;
+    /// Called once a timeout set through `Context::set_timeout` expires. Protocols that never
+    /// set a timeout can leave this out.
+    fn timeout<C>(&mut self, _ctx: &mut C) where C: Context<Write=Self::Output> {}
 }
 
 pub trait Context {
     type Write;
 
-    /// The write method can only be called once per stage. The object will be returned if
-    /// the object was not scheduled to be written.
+    /// Schedules an object to be written once the current stage finishes. Writes made in the same
+    /// stage are encoded in order. The object will be returned if it was not scheduled to be
+    /// written. The future resolves once the encoded object has been flushed from the
+    /// transport, or fails if the transport closes first.
     fn write(&mut self, obj: Self::Write) -> Result<Future<()>, Self::Write>;
+    /// Returns false while the write buffer is above its high watermark, or once the current
+    /// stage has used up its write budget. Writes are rejected until the protocol's `writable`
+    /// callback is called again.
+    fn is_writable(&self) -> bool;
+    /// Stops decoding incoming data once the current stage finishes. Data that has already
+    /// arrived stays buffered in the transport until reading is resumed.
+    fn pause_read(&mut self);
+    /// Resumes decoding after `pause_read`, starting with any data buffered in the meantime.
+    fn resume_read(&mut self);
+    /// Returns a handle that resumes reading from outside of the protocol's callbacks, such as
+    /// once a slow backend finishes on another thread.
+    fn resume_handle(&self) -> ResumeHandle;
+    /// Returns the ALPN protocol and peer certificates of a TLS connection, once its handshake is
+    /// done.
+    fn tls_info(&self) -> Option<&TlsInfo>;
+    /// Calls the protocol's `timeout` method once `timeout` has passed. Replaces any timeout set
+    /// earlier.
+    fn set_timeout(&mut self, timeout: Duration);
+    /// Closes the pipeline once the current stage finishes. Any writes scheduled in this stage
+    /// are still encoded and flushed before the transport is closed.
+    fn close(&mut self);
+}
+
+/// Owns a datagram socket. Datagrams are always received and sent whole.
+pub trait DatagramTransport {
+    fn spawned(&mut self);
+    /// Optional io error provided
+    fn closed(&mut self, err: Option<&io::Error>);
+    /// Receives the next datagram along with the address it came from. Returns `None` once no
+    /// more datagrams are waiting.
+    fn recv_from(&mut self) -> io::Result<Option<(&[u8], SocketAddr)>>;
+    /// Sends `datagram` to `addr`. Returns false if the socket cannot take it right now, in which
+    /// case it should be sent again once the socket is writable.
+    fn send_to(&mut self, datagram: &[u8], addr: &SocketAddr) -> io::Result<bool>;
+}
+
+pub trait DatagramProtocol {
+    type Output;
+    type Input;
+
+    /// Called once the transport is ready. The protocol may close the pipeline from here.
+    fn spawned<C>(&mut self, ctx: &mut C) where C: DatagramContext;
+    /// Optional io error provided
+    fn closed<C>(&mut self, ctx: &mut C, err: Option<&io::Error>) where C: DatagramContext;
+    /// Called for every decoded datagram, along with the address it was received from.
+    fn received_data<C>(&mut self, ctx: &mut C, data: Self::Input, addr: SocketAddr) where C: DatagramContext<Write=Self::Output>;
+    /// Called once spawned, and whenever datagrams the socket could not take right away have all
+    /// been sent.
+    fn writable<C>(&mut self, ctx: &mut C) where C: DatagramContext<Write=Self::Output>;
+}
+
+pub trait DatagramContext {
+    type Write;
+
+    /// Schedules an object to be sent as a single datagram to `addr` once the current stage
+    /// finishes. The object will be returned if it was not scheduled to be written. The future
+    /// resolves once the datagram has been sent.
+    fn write_to(&mut self, obj: Self::Write, addr: SocketAddr) -> Result<Future<()>, Self::Write>;
+    /// Closes the pipeline once the current stage finishes.
     fn close(&mut self);
 }
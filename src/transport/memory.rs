@@ -0,0 +1,308 @@
+//! In-memory duplex streams, for tests and for running pipelines in-process.
+//!
+//! Memory streams are not registered with an event loop. Pipelines on top of them are driven by
+//! calling `readable` and `writable` directly.
+
+use traits::*;
+use netbuf::Buf;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Bytes travelling in one direction.
+struct Pipe {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn new() -> Arc<Mutex<Pipe>> {
+        Arc::new(Mutex::new(Pipe {
+            data: VecDeque::new(),
+            closed: false,
+        }))
+    }
+}
+
+/// Returns two connected streams. Whatever one writes, the other reads.
+pub fn pair() -> (MemoryStream, MemoryStream) {
+    let first = Pipe::new();
+    let second = Pipe::new();
+    (MemoryStream::new(first.clone(), second.clone()), MemoryStream::new(second, first))
+}
+
+/// One end of an in-memory duplex stream. Reads and writes can be limited in size, and errors
+/// injected, to simulate a real socket.
+pub struct MemoryStream {
+    inbound: Arc<Mutex<Pipe>>,
+    outbound: Arc<Mutex<Pipe>>,
+    read_buffer: Buf,
+    write_buffer: Buf,
+    max_read: Option<usize>,
+    max_write: Option<usize>,
+    read_error: Option<io::ErrorKind>,
+    write_error: Option<io::ErrorKind>,
+}
+
+impl MemoryStream {
+    fn new(inbound: Arc<Mutex<Pipe>>, outbound: Arc<Mutex<Pipe>>) -> MemoryStream {
+        MemoryStream {
+            inbound,
+            outbound,
+            read_buffer: Buf::new(),
+            write_buffer: Buf::new(),
+            max_read: None,
+            max_write: None,
+            read_error: None,
+            write_error: None,
+        }
+    }
+
+    /// Limits how many bytes a single `read` takes from the peer. Unlimited by default.
+    pub fn set_max_read(&mut self, max: Option<usize>) {
+        self.max_read = max;
+    }
+
+    /// Limits how many bytes a single `writable` hands to the peer. Unlimited by default.
+    pub fn set_max_write(&mut self, max: Option<usize>) {
+        self.max_write = max;
+    }
+
+    /// Fails the next `read` with an error of the given kind.
+    pub fn fail_next_read(&mut self, kind: io::ErrorKind) {
+        self.read_error = Some(kind);
+    }
+
+    /// Fails the next `writable` with an error of the given kind.
+    pub fn fail_next_write(&mut self, kind: io::ErrorKind) {
+        self.write_error = Some(kind);
+    }
+
+}
+
+impl Drop for MemoryStream {
+    /// Closes both directions, like dropping a socket.
+    fn drop(&mut self) {
+        self.outbound.lock().expect("lock poisoned").closed = true;
+        self.inbound.lock().expect("lock poisoned").closed = true;
+    }
+}
+
+impl Transport for MemoryStream {
+    type Buffer = Buf;
+
+    fn buffer(&mut self) -> &mut Self::Buffer {
+        &mut self.write_buffer
+    }
+
+    fn spawned(&mut self) {
+        debug!("spawned memory stream");
+    }
+
+    /// Like a socket, a graceful close only shuts down writing so the peer can still reply.
+    fn closed(&mut self, err: Option<&io::Error>) {
+        debug!("closing memory stream");
+        debug!("transport close: optional error: {:?}", err);
+
+        self.outbound.lock().expect("lock poisoned").closed = true;
+        if err.is_some() {
+            self.inbound.lock().expect("lock poisoned").closed = true;
+        }
+    }
+
+    fn read(&mut self) -> io::Result<&[u8]> {
+        if let Some(kind) = self.read_error.take() {
+            return Err(io::Error::new(kind, "injected read error"))
+        }
+
+        let mut inbound = self.inbound.lock().expect("lock poisoned");
+        let len = cmp::min(inbound.data.len(), self.max_read.unwrap_or(usize::MAX));
+        let (front, back) = inbound.data.as_slices();
+        let from_front = cmp::min(len, front.len());
+        self.read_buffer.extend(&front[..from_front]);
+        self.read_buffer.extend(&back[..len - from_front]);
+        inbound.data.drain(..len);
+
+        Ok(&self.read_buffer[..])
+    }
+
+    /// Tells transport that "bytes" number of bytes have been read
+    fn consume(&mut self, bytes: usize) {
+        self.read_buffer.consume(bytes)
+    }
+
+    fn buffered(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Hands the write buffer to the peer, up to the write limit.
+    fn writable(&mut self) -> io::Result<()> {
+        if let Some(kind) = self.write_error.take() {
+            return Err(io::Error::new(kind, "injected write error"))
+        }
+
+        if self.write_buffer.is_empty() {
+            return Ok(())
+        }
+
+        let mut outbound = self.outbound.lock().expect("lock poisoned");
+        if outbound.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "memory stream closed"))
+        }
+
+        let len = cmp::min(self.write_buffer.len(), self.max_write.unwrap_or(usize::MAX));
+        outbound.data.extend(&self.write_buffer[..len]);
+        self.write_buffer.consume(len);
+        Ok(())
+    }
+
+    /// Returns true once the peer has been closed and everything it wrote has been read.
+    fn is_peer_closed(&self) -> bool {
+        let inbound = self.inbound.lock().expect("lock poisoned");
+        inbound.closed && inbound.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use pipeline::Pipeline;
+    use std::io::Write;
+    use test_helpers::{FakeCodec, FakeProtocol, EchoProtocol, SendProtocol, StreamProtocol};
+
+    #[test]
+    fn test_memory_pair() {
+        let (mut first, mut second) = pair();
+
+        first.buffer().write_all(b"hello").unwrap();
+        expect(&first.writable()).to(be_ok());
+        expect(&first.buffered()).to(equal(&0));
+
+        expect(&second.read().unwrap().to_vec()).to(equal(&b"hello".to_vec()));
+        second.consume(5);
+        expect(&second.read().unwrap().len()).to(equal(&0));
+    }
+
+    #[test]
+    fn test_memory_short_read_partial_write() {
+        let (mut first, mut second) = pair();
+        first.set_max_write(Some(3));
+        second.set_max_read(Some(2));
+
+        first.buffer().write_all(b"hello").unwrap();
+        expect(&first.writable()).to(be_ok());
+        expect(&first.buffered()).to(equal(&2));
+
+        expect(&second.read().unwrap().to_vec()).to(equal(&b"he".to_vec()));
+        expect(&second.read().unwrap().to_vec()).to(equal(&b"hel".to_vec()));
+
+        expect(&first.writable()).to(be_ok());
+        second.set_max_read(None);
+        expect(&second.read().unwrap().to_vec()).to(equal(&b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_memory_injected_errors() {
+        let (mut first, _second) = pair();
+        first.fail_next_read(io::ErrorKind::ConnectionReset);
+        first.fail_next_write(io::ErrorKind::BrokenPipe);
+
+        expect(&first.read().unwrap_err().kind()).to(equal(&io::ErrorKind::ConnectionReset));
+        expect(&first.read()).to(be_ok());
+        expect(&first.writable().unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+        expect(&first.writable()).to(be_ok());
+    }
+
+    #[test]
+    fn test_memory_close() {
+        let (mut first, mut second) = pair();
+        first.closed(None);
+
+        // The peer can still reply after a graceful close
+        expect(&second.is_peer_closed()).to(equal(&true));
+        second.buffer().write_all(b"reply").unwrap();
+        expect(&second.writable()).to(be_ok());
+        expect(&first.read().unwrap().to_vec()).to(equal(&b"reply".to_vec()));
+
+        drop(first);
+        second.buffer().write_all(b"late").unwrap();
+        expect(&second.writable().unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_memory_close_with_error() {
+        let (mut first, mut second) = pair();
+        first.closed(Some(&io::Error::new(io::ErrorKind::ConnectionReset, "reset")));
+
+        second.buffer().write_all(b"late").unwrap();
+        expect(&second.writable().unwrap_err().kind()).to(equal(&io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_memory_pipelines_peer_close() {
+        let (client, server) = pair();
+        let protocol = FakeProtocol::new();
+        protocol.lock().unwrap().output = b"pong".to_vec();
+
+        let mut client = Pipeline::new(client, FakeCodec::new(), SendProtocol::new(b"ping".to_vec()));
+        let mut server = Pipeline::new(server, FakeCodec::new(), protocol.clone());
+
+        // The client sends its message and closes right away
+        client.spawned();
+        server.spawned();
+        expect(&client.is_closed()).to(equal(&true));
+
+        // The server still handles the message, replies, and then closes too
+        server.readable();
+        expect(&server.is_closed()).to(equal(&true));
+        {
+            let p = protocol.lock().unwrap();
+            expect(&p.input).to(equal(&b"ping".to_vec()));
+            expect(&p.closed).to(equal(&true));
+            expect(&p.error_kind).to(be_none());
+        }
+        // Written once when spawned and once in reply
+        expect(&client.transport_mut().read().unwrap().to_vec()).to(equal(&b"pongpong".to_vec()));
+    }
+
+    #[test]
+    fn test_memory_pipelines_peer_close_streaming() {
+        let (client, server) = pair();
+        let mut client = Pipeline::new(client, FakeCodec::new(), SendProtocol::new(b"ping".to_vec()));
+        let mut server = Pipeline::new(server, FakeCodec::new(), StreamProtocol::new(100));
+        server.set_write_budget(10);
+
+        // The client only closes its side, the reply can still be read
+        client.spawned();
+        server.spawned();
+        server.readable();
+        expect(&server.is_closed()).to(equal(&false));
+
+        // The reply is streamed a budget at a time, the server only closes after the last write
+        while server.is_write_pending() {
+            server.wakeup();
+        }
+        expect(&server.is_closed()).to(equal(&true));
+        let expected: Vec<u8> = (1..101).rev().collect();
+        expect(&client.transport_mut().read().unwrap().to_vec()).to(equal(&expected));
+    }
+
+    #[test]
+    fn test_memory_pipelines() {
+        let (client, server) = pair();
+        let protocol = FakeProtocol::new();
+        protocol.lock().unwrap().output = b"ping".to_vec();
+
+        let mut client = Pipeline::new(client, FakeCodec::new(), protocol.clone());
+        let mut server = Pipeline::new(server, FakeCodec::new(), EchoProtocol);
+
+        client.spawned();
+        server.spawned();
+        server.readable();
+        client.readable();
+
+        expect(&(protocol.lock().unwrap().input)).to(equal(&b"ping".to_vec()));
+    }
+}
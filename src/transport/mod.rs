@@ -1 +1,68 @@
 pub mod tcp;
+pub mod udp;
+pub mod tls;
+#[cfg(feature = "rustls")]
+pub mod rustls;
+pub mod memory;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(unix)]
+pub mod seqpacket;
+
+use netbuf::Buf;
+use std::io::{self, Read, Write};
+
+/// Reads everything `stream` has available into `buf`, stopping at EOF or once it would block.
+fn fill_buffer<R: Read>(buf: &mut Buf, stream: &mut R) -> io::Result<()> {
+    use std::io::ErrorKind::*;
+
+    loop {
+        match buf.read_from(stream) {
+            // EOF, the reactor will be notified of the hangup separately
+            Ok(0) => return Ok(()),
+            Ok(_) => {},
+            Err(e) => {
+                match e.kind() {
+                    WouldBlock => return Ok(()),
+                    Interrupted => {},
+                    _ => return Err(e),
+                }
+            },
+        }
+    }
+}
+
+/// Returns and clears the pending error on the socket behind `fd`, without taking ownership of
+/// it.
+#[cfg(unix)]
+fn take_socket_error(fd: ::std::os::unix::io::RawFd) -> io::Result<()> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixStream;
+
+    let socket = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(fd) });
+    match socket.take_error()? {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Writes as much of `buf` to `stream` as it accepts.
+fn flush_buffer<W: Write>(buf: &mut Buf, stream: &mut W) -> io::Result<()> {
+    use std::io::ErrorKind::*;
+
+    while !buf.is_empty() {
+        match buf.write_to(stream) {
+            Ok(0) => return Err(io::Error::new(WriteZero, "stream accepted no data")),
+            Ok(_) => {},
+            Err(e) => {
+                match e.kind() {
+                    WouldBlock => return Ok(()),
+                    Interrupted => {},
+                    _ => return Err(e),
+                }
+            },
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,274 @@
+//! A `TlsSession` backed by rustls, with the ring crypto provider.
+//!
+//! Configs are built with `client_config` and `server_config`, which also set the protocols
+//! offered through ALPN. Sessions are cheap to create from a shared config, one per connection.
+
+use transport::tls::TlsSession;
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+fn invalid_input<E>(e: E) -> io::Error
+where E: Into<Box<dyn Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// Builds a client config that trusts the DER encoded certificates in `roots` and offers the
+/// `alpn` protocols, most preferred first.
+pub fn client_config(roots: &[Vec<u8>], alpn: Vec<Vec<u8>>) -> io::Result<Arc<ClientConfig>> {
+    let mut store = RootCertStore::empty();
+    for root in roots {
+        store.add(CertificateDer::from(root.clone())).map_err(invalid_input)?;
+    }
+
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_input)?
+        .with_root_certificates(store)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn;
+    Ok(Arc::new(config))
+}
+
+/// Builds a server config that presents the DER encoded certificate `chain`, leaf first, with
+/// its PKCS#8 encoded private `key`. Clients are offered the first of their ALPN protocols that
+/// is also in `alpn`.
+pub fn server_config(chain: Vec<Vec<u8>>, key: Vec<u8>, alpn: Vec<Vec<u8>>) -> io::Result<Arc<ServerConfig>> {
+    let chain = chain.into_iter().map(CertificateDer::from).collect();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key));
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_input)?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(invalid_input)?;
+    config.alpn_protocols = alpn;
+    Ok(Arc::new(config))
+}
+
+/// A rustls client or server connection.
+pub struct RustlsSession {
+    conn: Connection,
+}
+
+impl RustlsSession {
+    /// Starts a client session to the server named `server_name`, which its certificate is
+    /// checked against.
+    pub fn client(config: Arc<ClientConfig>, server_name: &str) -> io::Result<RustlsSession> {
+        let name = ServerName::try_from(server_name.to_owned()).map_err(invalid_input)?;
+        let conn = ClientConnection::new(config, name).map_err(invalid_input)?;
+        Ok(RustlsSession {
+            conn: conn.into(),
+        })
+    }
+
+    pub fn server(config: Arc<ServerConfig>) -> io::Result<RustlsSession> {
+        let conn = ServerConnection::new(config).map_err(invalid_input)?;
+        Ok(RustlsSession {
+            conn: conn.into(),
+        })
+    }
+}
+
+impl Read for RustlsSession {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.conn.reader().read(buf)
+    }
+}
+
+impl Write for RustlsSession {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.conn.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.writer().flush()
+    }
+}
+
+impl TlsSession for RustlsSession {
+    fn read_tls(&mut self, rd: &mut dyn Read) -> io::Result<usize> {
+        self.conn.read_tls(rd)
+    }
+
+    fn write_tls(&mut self, wr: &mut dyn Write) -> io::Result<usize> {
+        self.conn.write_tls(wr)
+    }
+
+    fn process_new_packets(&mut self) -> io::Result<()> {
+        self.conn.process_new_packets()
+            .map(|_| ())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn wants_write(&self) -> bool {
+        self.conn.wants_write()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.conn.is_handshaking()
+    }
+
+    fn send_close_notify(&mut self) {
+        self.conn.send_close_notify()
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.conn.alpn_protocol()
+    }
+
+    fn peer_certificates(&self) -> Option<Vec<Vec<u8>>> {
+        self.conn.peer_certificates().map(|chain| chain.iter().map(|cert| cert.to_vec()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use rotor::mio::tcp::{TcpListener as MioTcpListener, TcpStream as MioTcpStream};
+    use reactor::AsyncTransport;
+    use pipeline::Pipeline;
+    use transport::memory;
+    use transport::tcp::TcpStream;
+    use transport::tls::{TlsStream, TlsInfo};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use test_helpers::{FakeCodec, FakeProtocol};
+
+    const CERT: &[u8] = include_bytes!("../test_helpers/certs/localhost.cert.der");
+    const KEY: &[u8] = include_bytes!("../test_helpers/certs/localhost.key.der");
+
+    fn wait_for_tls_info(protocol: &Arc<Mutex<FakeProtocol>>) -> Option<TlsInfo> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(ref info) = protocol.lock().unwrap().tls_info {
+                return Some(info.clone())
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        None
+    }
+
+    /// Runs a client and a server pipeline over loopback TCP, the client trusting `roots`.
+    fn run_loopback(roots: &[Vec<u8>]) -> (Arc<Mutex<FakeProtocol>>, Arc<Mutex<FakeProtocol>>) {
+        let listener = MioTcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client_stream = MioTcpStream::connect(&addr).unwrap();
+        let start = Instant::now();
+        let server_stream = loop {
+            if let Some((stream, _)) = listener.accept().unwrap() {
+                break stream
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no connection to accept");
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        let alpn = vec!(b"h2".to_vec(), b"http/1.1".to_vec());
+        let client_config = client_config(roots, alpn).unwrap();
+        let server_config = server_config(vec!(CERT.to_vec()), KEY.to_vec(), vec!(b"http/1.1".to_vec())).unwrap();
+        let client_session = RustlsSession::client(client_config, "localhost").unwrap();
+        let server_session = RustlsSession::server(server_config).unwrap();
+
+        let client = FakeProtocol::new();
+        let server = FakeProtocol::new();
+        client.lock().unwrap().output = b"ping".to_vec();
+        server.lock().unwrap().output = b"pong".to_vec();
+
+        let client_pipeline = Pipeline::new(TlsStream::new(TcpStream::new(client_stream), client_session),
+                                            FakeCodec::new(),
+                                            client.clone());
+        let server_pipeline = Pipeline::new(TlsStream::new(TcpStream::new(server_stream), server_session),
+                                            FakeCodec::new(),
+                                            server.clone());
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::connect(client_pipeline, Some(Duration::from_secs(5)), scope)
+            }).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::new(server_pipeline, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        (client, server)
+    }
+
+    #[test]
+    fn test_rustls_loopback() {
+        let (client, server) = run_loopback(&[CERT.to_vec()]);
+        let client_info = wait_for_tls_info(&client);
+        let server_info = wait_for_tls_info(&server);
+        // Both sides keep replying to each other, stop them
+        client.lock().unwrap().batch = 0;
+        server.lock().unwrap().batch = 0;
+
+        expect(&client_info).to(equal(&Some(TlsInfo {
+            alpn_protocol: Some(b"http/1.1".to_vec()),
+            peer_certificates: vec!(CERT.to_vec()),
+        })));
+        expect(&server_info).to(equal(&Some(TlsInfo {
+            alpn_protocol: Some(b"http/1.1".to_vec()),
+            peer_certificates: Vec::new(),
+        })));
+        expect(&client.lock().unwrap().input.starts_with(b"pong")).to(equal(&true));
+        expect(&server.lock().unwrap().input.starts_with(b"ping")).to(equal(&true));
+    }
+
+    #[test]
+    fn test_rustls_untrusted_certificate() {
+        let (client, server) = run_loopback(&[]);
+
+        let start = Instant::now();
+        while !client.lock().unwrap().closed && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let p = client.lock().unwrap();
+        expect(&p.error_kind).to(equal(&Some(io::ErrorKind::InvalidData)));
+        expect(&p.input.is_empty()).to(equal(&true));
+        expect(&server.lock().unwrap().input.is_empty()).to(equal(&true));
+    }
+
+    #[test]
+    fn test_rustls_bulk_write() {
+        let client_config = client_config(&[CERT.to_vec()], Vec::new()).unwrap();
+        let server_config = server_config(vec!(CERT.to_vec()), KEY.to_vec(), Vec::new()).unwrap();
+        let (client_stream, server_stream) = memory::pair();
+        let client_stream = TlsStream::new(client_stream, RustlsSession::client(client_config, "localhost").unwrap());
+        let server_stream = TlsStream::new(server_stream, RustlsSession::server(server_config).unwrap());
+
+        // Far more than the single record a session buffers
+        let data: Vec<u8> = (0..100 * 1024).map(|i| i as u8).collect();
+        let client = FakeProtocol::new();
+        let server = FakeProtocol::new();
+        client.lock().unwrap().output = data.clone();
+
+        let mut client_pipeline = Pipeline::new(client_stream, FakeCodec::new(), client.clone());
+        let mut server_pipeline = Pipeline::new(server_stream, FakeCodec::new(), server.clone());
+        client_pipeline.spawned();
+        server_pipeline.spawned();
+        for _ in 0..10 {
+            client_pipeline.readable();
+            server_pipeline.readable();
+        }
+
+        expect(&server_pipeline.is_closed()).to(equal(&false));
+        expect(&(server.lock().unwrap().input.len())).to(equal(&data.len()));
+        expect(&(server.lock().unwrap().input == data)).to(equal(&true));
+    }
+
+    #[test]
+    fn test_rustls_bad_key() {
+        let res = server_config(vec!(CERT.to_vec()), b"not a key".to_vec(), Vec::new());
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidInput));
+    }
+}
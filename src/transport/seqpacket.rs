@@ -0,0 +1,394 @@
+//! Unix domain seqpacket sockets. Like stream sockets they are connection oriented and reliable,
+//! but they keep message boundaries.
+//!
+//! Every write a codec makes is sent to the peer as one packet. Received packets are appended to
+//! the read buffer in order, so codecs work the same as on stream sockets and have to frame
+//! messages themselves.
+
+use traits::*;
+use reactor::{Socket, Listener};
+use transport::take_socket_error;
+use rotor::mio::{Evented, EventSet, PollOpt, Selector, Token};
+use rotor::mio::unix::EventedFd;
+use netbuf::Buf;
+use libc;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+
+/// Largest packet that can be received. Longer packets fail the read.
+pub const MAX_PACKET_LEN: usize = 65536;
+
+const BACKLOG: libc::c_int = 128;
+
+fn cvt(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+fn sockaddr(path: &Path) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.contains(&0) || bytes.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid unix socket path"))
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    let len = mem::size_of::<libc::sa_family_t>() + bytes.len() + 1;
+    Ok((addr, len as libc::socklen_t))
+}
+
+/// An owned, non-blocking socket file descriptor, closed when dropped.
+pub struct RawSocket {
+    fd: RawFd,
+}
+
+impl RawSocket {
+    fn new() -> io::Result<RawSocket> {
+        let fd = cvt(unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0) })?;
+        RawSocket::from_fd(fd)
+    }
+
+    /// Takes ownership of `fd` and makes it non-blocking.
+    fn from_fd(fd: RawFd) -> io::Result<RawSocket> {
+        let socket = RawSocket {
+            fd,
+        };
+        unsafe {
+            let flags = cvt(libc::fcntl(fd, libc::F_GETFL))?;
+            cvt(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+            cvt(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+        }
+        Ok(socket)
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl AsRawFd for RawSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Evented for RawSocket {
+    fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.fd).register(selector, token, interest, opts)
+    }
+
+    fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.fd).reregister(selector, token, interest, opts)
+    }
+
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        EventedFd(&self.fd).deregister(selector)
+    }
+}
+
+pub struct SeqPacketListener {
+    socket: RawSocket,
+}
+
+impl SeqPacketListener {
+    /// Binds to `path`, which must not exist yet.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<SeqPacketListener> {
+        let (addr, len) = sockaddr(path.as_ref())?;
+        let socket = RawSocket::new()?;
+        unsafe {
+            cvt(libc::bind(socket.fd, &addr as *const _ as *const libc::sockaddr, len))?;
+            cvt(libc::listen(socket.fd, BACKLOG))?;
+        }
+
+        Ok(SeqPacketListener {
+            socket,
+        })
+    }
+}
+
+impl Socket for SeqPacketListener {
+    type Evented = RawSocket;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.socket
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        take_socket_error(self.socket.fd)
+    }
+}
+
+impl Listener for SeqPacketListener {
+    type Transport = SeqPacketStream;
+
+    fn accept(&self) -> io::Result<Option<Self::Transport>> {
+        let fd = unsafe { libc::accept(self.socket.fd, ptr::null_mut(), ptr::null_mut()) };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(None)
+            }
+            return Err(e)
+        }
+
+        debug!("accepted seqpacket connection");
+        RawSocket::from_fd(fd).map(|socket| Some(SeqPacketStream::new(socket)))
+    }
+}
+
+pub struct SeqPacketStream {
+    socket: RawSocket,
+    read_buffer: Buf,
+    write_buffer: Buf,
+    // Where each packet in the write buffer ends, oldest first
+    packet_ends: VecDeque<usize>,
+    // Receives one packet at a time, with room to notice packets that are too long
+    packet: Vec<u8>,
+}
+
+impl SeqPacketStream {
+    fn new(socket: RawSocket) -> SeqPacketStream {
+        SeqPacketStream {
+            socket,
+            read_buffer: Buf::new(),
+            write_buffer: Buf::new(),
+            packet_ends: VecDeque::new(),
+            packet: vec!(0; MAX_PACKET_LEN + 1),
+        }
+    }
+
+    /// Starts a non-blocking connect to the socket at `path`. The stream is not usable until
+    /// the reactor reports the connection as established.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<SeqPacketStream> {
+        let (addr, len) = sockaddr(path.as_ref())?;
+        let socket = RawSocket::new()?;
+        let res = unsafe { libc::connect(socket.fd, &addr as *const _ as *const libc::sockaddr, len) };
+        if let Err(e) = cvt(res) {
+            if e.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(e)
+            }
+        }
+
+        Ok(SeqPacketStream::new(socket))
+    }
+
+    /// Returns two connected streams.
+    pub fn pair() -> io::Result<(SeqPacketStream, SeqPacketStream)> {
+        let mut fds = [0; 2];
+        cvt(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) })?;
+        let first = RawSocket::from_fd(fds[0]);
+        let second = RawSocket::from_fd(fds[1]);
+        Ok((SeqPacketStream::new(first?), SeqPacketStream::new(second?)))
+    }
+
+    /// Ends the packet being written, if anything was written since the last one ended.
+    fn end_packet(&mut self) {
+        let start = self.packet_ends.back().cloned().unwrap_or(0);
+        if self.write_buffer.len() > start {
+            self.packet_ends.push_back(self.write_buffer.len());
+        }
+    }
+}
+
+impl AsRawFd for SeqPacketStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.fd
+    }
+}
+
+impl Socket for SeqPacketStream {
+    type Evented = RawSocket;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.socket
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        take_socket_error(self.socket.fd)
+    }
+}
+
+impl Transport for SeqPacketStream {
+    type Buffer = Buf;
+
+    /// Returns the write buffer. Every call starts a new packet, pipelines call this once for
+    /// every write they encode.
+    fn buffer(&mut self) -> &mut Self::Buffer {
+        self.end_packet();
+        &mut self.write_buffer
+    }
+
+    fn spawned(&mut self) {
+        debug!("spawned seqpacket stream");
+    }
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        debug!("closing seqpacket stream");
+        debug!("transport close: optional error: {:?}", err);
+
+        // A graceful close only happens once the write buffer has been flushed
+        let how = if err.is_none() { libc::SHUT_WR } else { libc::SHUT_RDWR };
+        if let Err(e) = cvt(unsafe { libc::shutdown(self.socket.fd, how) }) {
+            error!("seqpacket transport: error closing: {}", e);
+        }
+    }
+
+    /// Receives every packet available, stopping at EOF or once it would block.
+    fn read(&mut self) -> io::Result<&[u8]> {
+        loop {
+            let res = unsafe {
+                libc::recv(self.socket.fd,
+                           self.packet.as_mut_ptr() as *mut libc::c_void,
+                           self.packet.len(),
+                           0)
+            };
+            if res < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                }
+            }
+
+            // EOF, the reactor will be notified of the hangup separately
+            let len = res as usize;
+            if len == 0 {
+                break
+            }
+            if len > MAX_PACKET_LEN {
+                let msg = format!("packet exceeds maximum length of {}", MAX_PACKET_LEN);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+            }
+            self.read_buffer.extend(&self.packet[..len]);
+        }
+
+        Ok(&self.read_buffer[..])
+    }
+
+    /// Tells transport that "bytes" number of bytes have been read
+    fn consume(&mut self, bytes: usize) {
+        self.read_buffer.consume(bytes)
+    }
+
+    fn buffered(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Sends whole packets until the socket stops taking them. A packet larger than the socket's
+    /// send buffer fails the write.
+    fn writable(&mut self) -> io::Result<()> {
+        self.end_packet();
+
+        while let Some(&end) = self.packet_ends.front() {
+            let res = unsafe {
+                libc::send(self.socket.fd,
+                           self.write_buffer[..end].as_ptr() as *const libc::c_void,
+                           end,
+                           0)
+            };
+            if res < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                }
+            }
+
+            self.write_buffer.consume(end);
+            self.packet_ends.pop_front();
+            for packet_end in self.packet_ends.iter_mut() {
+                *packet_end -= end;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use reactor::{Server, AsyncTransport};
+    use pipeline::Pipeline;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use test_helpers::{FakeCodec, FakeProtocol, EchoProtocol};
+
+    fn recv_packet(fd: RawFd) -> Vec<u8> {
+        let mut buf = [0u8; 64];
+        let len = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        buf[..len as usize].to_vec()
+    }
+
+    #[test]
+    fn test_seqpacket_write_boundaries() {
+        let (mut first, mut second) = SeqPacketStream::pair().unwrap();
+
+        first.buffer().write_all(b"ab").unwrap();
+        first.buffer().write_all(b"cd").unwrap();
+        expect(&first.writable()).to(be_ok());
+        expect(&first.buffered()).to(equal(&0));
+
+        // Each write arrived as its own packet
+        expect(&recv_packet(second.as_raw_fd())).to(equal(&b"ab".to_vec()));
+        expect(&second.read().unwrap().to_vec()).to(equal(&b"cd".to_vec()));
+    }
+
+    #[test]
+    fn test_seqpacket_server_echo() {
+        let path = env::temp_dir().join(format!("nexus-seqpacket-echo-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = SeqPacketListener::bind(&path).unwrap();
+
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                Server::<(), _, _, _, _, _>::new(listener, FakeCodec::new, || EchoProtocol, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let protocol = FakeProtocol::new();
+        protocol.lock().unwrap().output = b"hello".to_vec();
+        let stream = SeqPacketStream::connect(&path).unwrap();
+        let pipeline = Pipeline::new(stream, FakeCodec::new(), protocol.clone());
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::connect(pipeline, Some(Duration::from_secs(5)), scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let start = Instant::now();
+        while protocol.lock().unwrap().input.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = fs::remove_file(&path);
+        // The protocol replies to every echo, so only the first one is certain
+        expect(&protocol.lock().unwrap().input.starts_with(b"hello")).to(equal(&true));
+    }
+}
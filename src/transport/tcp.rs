@@ -1,21 +1,98 @@
 use traits::*;
+use reactor::{Socket, Listener};
+use transport::{fill_buffer, flush_buffer};
 use rotor::mio::tcp::TcpStream as MioTcpStream;
+use rotor::mio::tcp::TcpListener as MioTcpListener;
 use rotor::mio::tcp::Shutdown;
 use netbuf::Buf;
 use std::io;
+use std::net::SocketAddr;
+
+pub struct TcpListener {
+    listener: MioTcpListener,
+}
+
+impl TcpListener {
+    pub fn new(listener: MioTcpListener) -> TcpListener {
+        TcpListener {
+            listener,
+        }
+    }
+
+    pub fn bind(addr: &SocketAddr) -> io::Result<TcpListener> {
+        MioTcpListener::bind(addr).map(TcpListener::new)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl Socket for TcpListener {
+    type Evented = MioTcpListener;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.listener
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        self.listener.take_socket_error()
+    }
+}
+
+impl Listener for TcpListener {
+    type Transport = TcpStream;
+
+    fn accept(&self) -> io::Result<Option<Self::Transport>> {
+        let accepted = self.listener.accept()?;
+        Ok(accepted.map(|(stream, addr)| {
+            debug!("accepted tcp connection from {}", addr);
+            TcpStream::new(stream)
+        }))
+    }
+}
 
 pub struct TcpStream {
     stream: MioTcpStream,
     read_buffer: Buf,
+    write_buffer: Buf,
+}
+
+impl TcpStream {
+    pub fn new(stream: MioTcpStream) -> TcpStream {
+        TcpStream {
+            stream,
+            read_buffer: Buf::new(),
+            write_buffer: Buf::new(),
+        }
+    }
+
+    /// Starts a non-blocking connect to `addr`. The stream is not usable until the reactor
+    /// reports the connection as established.
+    pub fn connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+        MioTcpStream::connect(addr).map(TcpStream::new)
+    }
+}
+
+impl Socket for TcpStream {
+    type Evented = MioTcpStream;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.stream
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        self.stream.take_socket_error()
+    }
 }
 
 impl Transport for TcpStream {
-    type Buffer = MioTcpStream;
+    type Buffer = Buf;
 
-    /// Returns a buffer object that will write data to the underlying socket. This is used by the
-    /// Codecs in order to efficiently write data without copying.
+    /// Returns the write buffer. Data written to it is sent once the socket is writable, so
+    /// codecs never see a partial write.
     fn buffer(&mut self) -> &mut Self::Buffer {
-        &mut self.stream
+        &mut self.write_buffer
     }
 
     fn spawned(&mut self) {
@@ -26,32 +103,17 @@ impl Transport for TcpStream {
         debug!("closing tcp stream");
         debug!("transport close: optional error: {:?}", err);
 
-        self.stream.shutdown(Shutdown::Both).map_err(|e| {
+        // A graceful close only happens once the write buffer has been flushed, so the peer
+        // gets everything before the end of the stream
+        let how = if err.is_none() { Shutdown::Write } else { Shutdown::Both };
+        if let Err(e) = self.stream.shutdown(how) {
             error!("tcp transport: error closing: {}", e);
-        });
+        }
     }
 
     fn read(&mut self) -> io::Result<&[u8]> {
-        use std::io::ErrorKind::*;
-
-        let stream = &mut self.stream;
-        let buf = &mut self.read_buffer;
-        loop {
-            match buf.read_from(stream) {
-                Ok(_) => {},
-                Err(e) => {
-                    match e.kind() {
-                        WouldBlock => {
-                            return Ok(&buf[..])
-                        },
-                        Interrupted => {},
-                        _ => {
-                            return Err(e)
-                        },
-                    }
-                },
-            }
-        }
+        fill_buffer(&mut self.read_buffer, &mut self.stream)?;
+        Ok(&self.read_buffer[..])
     }
 
     /// Tells transport that "bytes" number of bytes have been read
@@ -59,8 +121,12 @@ impl Transport for TcpStream {
         self.read_buffer.consume(bytes)
     }
 
-    /// Called when socket changes state to being writable.
-    fn writable(&mut self) {
-        debug!("writable tcp stream");
+    fn buffered(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Writes as much of the write buffer to the socket as it accepts.
+    fn writable(&mut self) -> io::Result<()> {
+        flush_buffer(&mut self.write_buffer, &mut self.stream)
     }
 }
@@ -0,0 +1,314 @@
+//! TLS over any byte stream `Transport`.
+//!
+//! The record layer is provided by a `TlsSession` implementation, typically a thin wrapper
+//! around a TLS library's client or server session. `transport::rustls` provides one with the
+//! `rustls` feature enabled. `TlsStream` only moves bytes between that
+//! session and the wrapped transport, so the same adapter serves both client and server mode.
+
+use traits::*;
+use reactor::Socket;
+use netbuf::Buf;
+use std::io::{self, Read, Write};
+
+/// A TLS client or server session. Plaintext is read from and written to the session through
+/// its `Read` and `Write` impls, records go through `read_tls` and `write_tls`.
+pub trait TlsSession: Read + Write {
+    /// Reads TLS records from `rd`, returning the number of bytes taken.
+    fn read_tls(&mut self, rd: &mut dyn Read) -> io::Result<usize>;
+    /// Writes pending TLS records to `wr`, returning the number of bytes written.
+    fn write_tls(&mut self, wr: &mut dyn Write) -> io::Result<usize>;
+    /// Processes the records read so far. An error means the session cannot continue.
+    fn process_new_packets(&mut self) -> io::Result<()>;
+    /// Returns true if the session has records waiting to be written.
+    fn wants_write(&self) -> bool;
+    fn is_handshaking(&self) -> bool;
+    /// Queues a close notification to the peer.
+    fn send_close_notify(&mut self);
+    /// Returns the protocol agreed on through ALPN, once the handshake is done.
+    fn alpn_protocol(&self) -> Option<&[u8]>;
+    /// Returns the DER encoded certificate chain presented by the peer, if any.
+    fn peer_certificates(&self) -> Option<Vec<Vec<u8>>>;
+}
+
+/// What a completed handshake established about the peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    /// The protocol agreed on through ALPN, if any.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The DER encoded certificate chain presented by the peer, leaf first. Empty if the peer
+    /// did not present one.
+    pub peer_certificates: Vec<Vec<u8>>,
+}
+
+/// Encrypts everything codecs write and decrypts everything read through the wrapped transport.
+pub struct TlsStream<T, S> {
+    transport: T,
+    session: S,
+    read_buffer: Buf,
+    write_buffer: Buf,
+}
+
+impl<T, S> TlsStream<T, S>
+where T: Transport,
+      T::Buffer: Write,
+      S: TlsSession
+{
+    /// Wraps `transport`. Whether this is the client or the server side is decided by
+    /// `session`.
+    pub fn new(transport: T, session: S) -> TlsStream<T, S> {
+        TlsStream {
+            transport,
+            session,
+            read_buffer: Buf::new(),
+            write_buffer: Buf::new(),
+        }
+    }
+
+    pub fn session(&self) -> &S {
+        &self.session
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Hands buffered plaintext to the session once the handshake allows it, then moves any
+    /// records it produced to the wrapped transport and flushes it. Sessions only take so much
+    /// plaintext at once, so this goes on for as long as records keep moving.
+    fn pump(&mut self) -> io::Result<()> {
+        loop {
+            if !self.session.is_handshaking() {
+                while !self.write_buffer.is_empty() {
+                    let written = self.session.write(&self.write_buffer[..])?;
+                    if written == 0 {
+                        break
+                    }
+                    self.write_buffer.consume(written);
+                }
+            }
+
+            let mut moved = 0;
+            while self.session.wants_write() {
+                let written = self.session.write_tls(self.transport.buffer())?;
+                if written == 0 {
+                    break
+                }
+                moved += written;
+            }
+            self.transport.writable()?;
+
+            if moved == 0 || self.write_buffer.is_empty() || self.session.is_handshaking() {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Feeds the transport's records to the session and collects the resulting plaintext.
+    /// Sessions only buffer about one record, so records are processed as they are taken.
+    fn decrypt(&mut self) -> io::Result<()> {
+        let consumed = {
+            let mut records = self.transport.read()?;
+            let len = records.len();
+            while !records.is_empty() {
+                let taken = self.session.read_tls(&mut records)?;
+                self.session.process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                read_plaintext(&mut self.read_buffer, &mut self.session)?;
+                if taken == 0 {
+                    break
+                }
+            }
+            len - records.len()
+        };
+        self.transport.consume(consumed);
+        Ok(())
+    }
+}
+
+/// Moves all plaintext the session has decrypted into `buf`.
+fn read_plaintext<S: Read>(buf: &mut Buf, session: &mut S) -> io::Result<()> {
+    loop {
+        match buf.read_from(session) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl<T, S> Socket for TlsStream<T, S>
+where T: Socket
+{
+    type Evented = T::Evented;
+
+    fn evented(&self) -> &Self::Evented {
+        self.transport.evented()
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        self.transport.take_socket_error()
+    }
+}
+
+impl<T, S> Transport for TlsStream<T, S>
+where T: Transport,
+      T::Buffer: Write,
+      S: TlsSession
+{
+    type Buffer = Buf;
+
+    /// Returns the plaintext write buffer. It is encrypted once the handshake is done.
+    fn buffer(&mut self) -> &mut Self::Buffer {
+        &mut self.write_buffer
+    }
+
+    /// Starts the handshake, if the session is the one to begin it.
+    fn spawned(&mut self) {
+        self.transport.spawned();
+        if let Err(e) = self.pump() {
+            error!("tls transport: error starting handshake: {}", e);
+        }
+    }
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        if err.is_none() {
+            self.session.send_close_notify();
+            if let Err(e) = self.pump() {
+                debug!("tls transport: could not send close notify: {}", e);
+            }
+        }
+        self.transport.closed(err);
+    }
+
+    fn read(&mut self) -> io::Result<&[u8]> {
+        self.decrypt()?;
+        // Reading may have moved the handshake along or unblocked buffered plaintext
+        self.pump()?;
+        Ok(&self.read_buffer[..])
+    }
+
+    /// Tells transport that "bytes" number of bytes have been read
+    fn consume(&mut self, bytes: usize) {
+        self.read_buffer.consume(bytes)
+    }
+
+    /// Counts both unencrypted plaintext and records the wrapped transport has not flushed.
+    /// Records are larger than the plaintext they carry, so this errs on the side of holding
+    /// back write futures.
+    fn buffered(&self) -> usize {
+        self.write_buffer.len() + self.transport.buffered()
+    }
+
+    fn writable(&mut self) -> io::Result<()> {
+        self.pump()
+    }
+
+    fn is_peer_closed(&self) -> bool {
+        self.transport.is_peer_closed()
+    }
+
+    fn tls_info(&self) -> Option<TlsInfo> {
+        if self.session.is_handshaking() {
+            return None
+        }
+
+        Some(TlsInfo {
+            alpn_protocol: self.session.alpn_protocol().map(|p| p.to_vec()),
+            peer_certificates: self.session.peer_certificates().unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use pipeline::Pipeline;
+    use std::io;
+    use test_helpers::{FakeTransport, TransportAssertions, FakeTlsSession, FakeCodec, FakeProtocol};
+
+    fn encrypt(plaintext: &[u8]) -> Vec<u8> {
+        plaintext.iter().map(|b| b ^ FakeTlsSession::KEY).collect()
+    }
+
+    fn records(plaintext: &[u8]) -> Vec<u8> {
+        let mut records = vec!(FakeTlsSession::HELLO);
+        records.extend(encrypt(plaintext));
+        records
+    }
+
+    #[test]
+    fn test_tls_stream_read() {
+        let mut vec = records(b"abc");
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let mut stream = TlsStream::new(transport, FakeTlsSession::new());
+
+        let read = stream.read().unwrap().to_vec();
+        expect(&read).to(equal(&b"abc".to_vec()));
+        expect(&stream.session().is_handshaking()).to(equal(&false));
+
+        stream.consume(3);
+        expect(&stream.read().unwrap().len()).to(equal(&0));
+    }
+
+    #[test]
+    fn test_tls_stream_write_waits_for_handshake() {
+        let mut vec = Vec::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let mut stream = TlsStream::new(transport, FakeTlsSession::new());
+            stream.spawned();
+
+            stream.buffer().write_all(b"xyz").unwrap();
+            expect(&stream.writable()).to(be_ok());
+            expect(&stream.buffered()).to(equal(&3));
+        }
+
+        // Only the hello goes out before the handshake completes
+        expect(&vec).to(equal(&vec!(FakeTlsSession::HELLO)));
+    }
+
+    #[test]
+    fn test_tls_stream_bad_record() {
+        let mut vec = vec!(0);
+        let assertions = TransportAssertions::new();
+        let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+        let mut stream = TlsStream::new(transport, FakeTlsSession::new());
+
+        let res = stream.read();
+        expect(&res.unwrap_err().kind()).to(equal(&io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_tls_stream_pipeline() {
+        let mut vec = records(b"ping");
+        let protocol = FakeProtocol::new();
+        let assertions = TransportAssertions::new();
+        {
+            let transport = FakeTransport::new(&mut vec, assertions.clone(), None);
+            let stream = TlsStream::new(transport, FakeTlsSession::new());
+            let mut pipeline = Pipeline::new(stream, FakeCodec::new(), protocol.clone());
+            protocol.lock().unwrap().output = b"pong".to_vec();
+
+            pipeline.spawned();
+            pipeline.readable();
+        }
+
+        // The reply written on spawn is held back until the handshake is done, and followed by
+        // the reply to the decrypted request
+        let mut expected = records(b"pong");
+        expected.extend(encrypt(b"pong"));
+        expect(&vec).to(equal(&expected));
+
+        let mut p = protocol.lock().unwrap();
+        expect(&p.input).to(equal(&b"ping".to_vec()));
+        expect(&(p.future.take().unwrap().get())).to(be_ok());
+        expect(&p.tls_info).to(equal(&Some(TlsInfo {
+            alpn_protocol: None,
+            peer_certificates: Vec::new(),
+        })));
+    }
+}
@@ -0,0 +1,77 @@
+use traits::*;
+use reactor::Socket;
+use rotor::mio::udp::UdpSocket as MioUdpSocket;
+use std::io;
+use std::net::SocketAddr;
+
+/// Large enough for any UDP datagram.
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+pub struct UdpSocket {
+    socket: MioUdpSocket,
+    recv_buffer: Vec<u8>,
+}
+
+impl UdpSocket {
+    pub fn new(socket: MioUdpSocket) -> UdpSocket {
+        UdpSocket {
+            socket,
+            recv_buffer: vec![0; MAX_DATAGRAM_LEN],
+        }
+    }
+
+    pub fn bind(addr: &SocketAddr) -> io::Result<UdpSocket> {
+        MioUdpSocket::bound(addr).map(UdpSocket::new)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl Socket for UdpSocket {
+    type Evented = MioUdpSocket;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.socket
+    }
+
+    /// mio does not expose pending errors on udp sockets, they surface on the next send or
+    /// receive instead.
+    fn take_socket_error(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl DatagramTransport for UdpSocket {
+    fn spawned(&mut self) {
+        debug!("spawned udp socket");
+    }
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        debug!("closing udp socket");
+        debug!("transport close: optional error: {:?}", err);
+    }
+
+    fn recv_from(&mut self) -> io::Result<Option<(&[u8], SocketAddr)>> {
+        loop {
+            match self.socket.recv_from(&mut self.recv_buffer[..]) {
+                Ok(Some((len, addr))) => return Ok(Some((&self.recv_buffer[..len], addr))),
+                Ok(None) => return Ok(None),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn send_to(&mut self, datagram: &[u8], addr: &SocketAddr) -> io::Result<bool> {
+        loop {
+            match self.socket.send_to(datagram, addr) {
+                Ok(Some(_)) => return Ok(true),
+                Ok(None) => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
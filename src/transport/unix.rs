@@ -0,0 +1,203 @@
+//! Unix domain stream sockets. Seqpacket sockets live in `transport::seqpacket`.
+
+use traits::*;
+use reactor::{Socket, Listener};
+use transport::{fill_buffer, flush_buffer, take_socket_error};
+use rotor::mio::unix::UnixStream as MioUnixStream;
+use rotor::mio::unix::UnixListener as MioUnixListener;
+use netbuf::Buf;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::Path;
+
+pub struct UnixListener {
+    listener: MioUnixListener,
+}
+
+impl UnixListener {
+    pub fn new(listener: MioUnixListener) -> UnixListener {
+        UnixListener {
+            listener,
+        }
+    }
+
+    /// Binds to `path`, which must not exist yet.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        MioUnixListener::bind(path.as_ref()).map(UnixListener::new)
+    }
+}
+
+impl Socket for UnixListener {
+    type Evented = MioUnixListener;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.listener
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        take_socket_error(self.listener.as_raw_fd())
+    }
+}
+
+impl Listener for UnixListener {
+    type Transport = UnixStream;
+
+    fn accept(&self) -> io::Result<Option<Self::Transport>> {
+        let accepted = self.listener.accept()?;
+        Ok(accepted.map(|stream| {
+            debug!("accepted unix connection");
+            UnixStream::new(stream)
+        }))
+    }
+}
+
+pub struct UnixStream {
+    stream: MioUnixStream,
+    read_buffer: Buf,
+    write_buffer: Buf,
+}
+
+impl UnixStream {
+    pub fn new(stream: MioUnixStream) -> UnixStream {
+        UnixStream {
+            stream,
+            read_buffer: Buf::new(),
+            write_buffer: Buf::new(),
+        }
+    }
+
+    /// Starts a non-blocking connect to the socket at `path`. The stream is not usable until
+    /// the reactor reports the connection as established.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
+        MioUnixStream::connect(path.as_ref()).map(UnixStream::new)
+    }
+}
+
+impl Socket for UnixStream {
+    type Evented = MioUnixStream;
+
+    fn evented(&self) -> &Self::Evented {
+        &self.stream
+    }
+
+    fn take_socket_error(&self) -> io::Result<()> {
+        take_socket_error(self.stream.as_raw_fd())
+    }
+}
+
+impl Transport for UnixStream {
+    type Buffer = Buf;
+
+    /// Returns the write buffer. Data written to it is sent once the socket is writable.
+    fn buffer(&mut self) -> &mut Self::Buffer {
+        &mut self.write_buffer
+    }
+
+    fn spawned(&mut self) {
+        debug!("spawned unix stream");
+    }
+
+    fn closed(&mut self, err: Option<&io::Error>) {
+        debug!("closing unix stream");
+        debug!("transport close: optional error: {:?}", err);
+
+        let stream = ManuallyDrop::new(unsafe { StdUnixStream::from_raw_fd(self.stream.as_raw_fd()) });
+        // A graceful close only happens once the write buffer has been flushed
+        let how = if err.is_none() { Shutdown::Write } else { Shutdown::Both };
+        if let Err(e) = stream.shutdown(how) {
+            error!("unix transport: error closing: {}", e);
+        }
+    }
+
+    fn read(&mut self) -> io::Result<&[u8]> {
+        fill_buffer(&mut self.read_buffer, &mut self.stream)?;
+        Ok(&self.read_buffer[..])
+    }
+
+    /// Tells transport that "bytes" number of bytes have been read
+    fn consume(&mut self, bytes: usize) {
+        self.read_buffer.consume(bytes)
+    }
+
+    fn buffered(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Writes as much of the write buffer to the socket as it accepts.
+    fn writable(&mut self) -> io::Result<()> {
+        flush_buffer(&mut self.write_buffer, &mut self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrous::dsl::*;
+    use rotor::{Loop, Config};
+    use reactor::{Server, AsyncTransport};
+    use pipeline::Pipeline;
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::{UnixStream as StdUnixStream, UnixListener as StdUnixListener};
+    use std::process;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use test_helpers::{FakeCodec, FakeProtocol, EchoProtocol};
+
+    #[test]
+    fn test_unix_server_echo() {
+        let path = env::temp_dir().join(format!("nexus-unix-echo-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                Server::<(), _, _, _, _, _>::new(listener, FakeCodec::new, || EchoProtocol, scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let mut conn = StdUnixStream::connect(&path).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        conn.write_all(b"hello").unwrap();
+
+        let mut buf = [0u8; 5];
+        let res = conn.read_exact(&mut buf);
+        let _ = fs::remove_file(&path);
+        expect(&res).to(be_ok());
+        expect(&&buf[..]).to(equal(&&b"hello"[..]));
+    }
+
+    #[test]
+    fn test_unix_stream_connect() {
+        let path = env::temp_dir().join(format!("nexus-unix-connect-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = StdUnixListener::bind(&path).unwrap();
+        let protocol = FakeProtocol::new();
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let pipeline = Pipeline::new(stream, FakeCodec::new(), protocol.clone());
+        thread::spawn(move || {
+            let mut event_loop = Loop::new(&Config::new()).unwrap();
+            event_loop.add_machine_with(|scope| {
+                AsyncTransport::<(), _, _, _>::connect(pipeline, Some(Duration::from_secs(5)), scope)
+            }).unwrap();
+            event_loop.run(()).unwrap();
+        });
+
+        let _conn = listener.accept().unwrap();
+        let _ = fs::remove_file(&path);
+
+        let start = Instant::now();
+        while !protocol.lock().unwrap().spawned && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        expect(&protocol.lock().unwrap().spawned).to(equal(&true));
+        expect(&protocol.lock().unwrap().closed).to(equal(&false));
+    }
+}